@@ -1,43 +1,81 @@
+pub mod cache;
 mod exception;
 mod mmu;
+// Not every BSP starts its cores through PSCI.
+#[allow(dead_code)]
+pub mod psci;
 pub mod sync;
 mod time;
 
-use crate::{bsp, interface};
+use crate::{bsp, interface, memory};
+use core::ptr;
 use cortex_a::{asm, regs::*};
-pub use time::sleep;
+pub use time::{sleep, Delay};
 
 /// Nice and nite activation thanks to rust's zero-abstraction.
-unsafe fn activate_other_cores() {
-    bsp::SLAVE_CORES_WAKEUP_ADDR.iter().for_each(|&addr| {
+///
+/// Wakes the slave cores spinning on the spin table entries at the physical `wakeup_addrs`. They
+/// spin with their MMU and caches off, so the entry point is written through the upper half alias
+/// of the wakeup addresses and cleaned to memory before they are woken.
+#[allow(dead_code)]
+pub unsafe fn activate_other_cores(wakeup_addrs: &[u64]) {
+    let entry = phys_entry_point();
+
+    wakeup_addrs.iter().for_each(|&addr| {
         // Get the address to activate that core.
-        let dest: *mut u64 = addr as *mut u64;
+        let dest = memory::kernel_phys_to_virt(addr as usize) as *mut u64;
         // Store _start function address as slave core entry point.
-        *dest = _start as *const () as u64;
+        core::ptr::write_volatile(dest, entry);
+        asm!("dc civac, $0" :: "r"(dest) : "memory" : "volatile");
     });
+    asm!("dsb sy" ::: "memory" : "volatile");
+
     // Activate all cores at once!
     asm!("sev");
 }
 
+/// Physical address of `_start`, where cores enter the kernel with their MMU off.
+pub fn phys_entry_point() -> u64 {
+    memory::kernel_virt_to_phys(_start as *const () as usize) as u64
+}
+
+/// Physical address of the device tree blob the firmware passed to the boot core in x0.
+///
+/// Written before `.bss` is zeroed, so the initializer must keep it out of `.bss`.
+static mut BOOT_DTB_PHYS_ADDR: usize = usize::MAX;
+
+/// Return the physical address of the device tree blob passed by the firmware, if any.
+pub fn boot_dtb_phys_addr() -> Option<usize> {
+    match unsafe { ptr::read_volatile(&BOOT_DTB_PHYS_ADDR) } {
+        0 | usize::MAX => None,
+        addr => Some(addr),
+    }
+}
+
 #[no_mangle]
-pub unsafe extern "C" fn _start() -> ! {
+pub unsafe extern "C" fn _start(dtb_phys_addr: usize) -> ! {
     use crate::runtime_init::{master_core_init, other_cores_init};
 
-    // Activation has to be done in EL2. Do this while we are still this privilaged.
-    activate_other_cores();
-
+    // The MMU is off, so hand over physical addresses. The kernel is linked to the upper half.
     let id = get_core_id();
     match id {
         // Core 0: Master core
-        0b00 => el2_to_el1_transition(
-            master_core_init as *const () as u64,
-            bsp::BOOT_CORE_STACK_START,
-        ),
+        0b00 => {
+            let dtb_addr_ptr =
+                memory::kernel_virt_to_phys(&mut BOOT_DTB_PHYS_ADDR as *mut usize as usize)
+                    as *mut usize;
+            ptr::write_volatile(dtb_addr_ptr, dtb_phys_addr);
+
+            el2_to_el1_transition(
+                memory::kernel_virt_to_phys(master_core_init as *const () as usize) as u64,
+                bsp::BOOT_CORE_STACK_START,
+            )
+        }
 
         // Core 1-3: Slave core
         0b01 | 0b10 | 0b11 => el2_to_el1_transition(
-            other_cores_init as *const () as u64,
-            (bsp::SLAVE_STACK_PREAMBLE | id) << bsp::SLAVE_STACK_SHIFT,
+            memory::kernel_virt_to_phys(other_cores_init as *const () as usize) as u64,
+            bsp::slave_stack_start(id),
         ),
 
         // Should not happen
@@ -140,10 +178,69 @@ pub mod state {
 }
 
 static MMU: mmu::MMU = mmu::MMU;
+pub fn mmu() -> &'static impl interface::mm::MMU {
+    &MMU
+}
+
+pub use mmu::{populate_boot_page_tables, PAGE_SIZE};
+
+/// Turn on the MMU and continue execution at `next`'s alias in the upper half, with the stack
+/// pointer moved there as well.
+///
+/// # Safety
+///
+/// - Must be called with the MMU off, from physical addresses.
+/// - The page tables must already hold at least the boot mapping.
+pub unsafe fn enable_mmu_and_switch_to_high_half(next: unsafe fn() -> !) -> ! {
+    use crate::interface::mm::MMU;
+
+    // Nothing can be printed before the switch, so just park the core.
+    if MMU.enable().is_err() {
+        wait_forever(get_core_id());
+    }
+
+    let stack_pointer = memory::kernel_phys_to_virt(SP.get() as usize);
+    let next_addr = memory::kernel_phys_to_virt(next as usize);
+
+    asm!("mov sp, $0
+          br  $1"
+         :: "r"(stack_pointer), "r"(next_addr) : "memory" : "volatile");
+
+    // Not reached.
+    wait_forever(get_core_id())
+}
+
+/// Stop translating lower half addresses on the calling core. Must be called once the core runs
+/// from the upper half.
+pub unsafe fn release_lower_half() {
+    use crate::interface::mm::MMU;
+
+    MMU.release_lower_half();
+}
+
+/// Refine the boot mapping according to the BSP's virtual memory layout, after checking that the
+/// layout is sane. The parts fixed at build time were checked then already; this catches the heap
+/// range, which depends on the device tree.
 pub unsafe fn init_mmu() {
     use crate::interface::mm::MMU;
 
+    if let Err(err) = bsp::virt_mem_layout().validate(PAGE_SIZE) {
+        panic!("Invalid virtual memory layout: {}", err);
+    }
+
     if let Err(err_msg) = MMU.init() {
         panic!("MMU: {}", err_msg);
     }
+
+    // The console's alias in the boot mapping is gone, so remap it before anything can panic.
+    if let Err(err) = bsp::map_panic_console_out() {
+        panic!("Panic console: {}", err);
+    }
+
+    if let Some(virt_addr) = MMU.find_writable_executable_page() {
+        panic!(
+            "MMU: page {:#018x} is both writable and executable",
+            virt_addr
+        );
+    }
 }
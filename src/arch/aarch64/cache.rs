@@ -0,0 +1,69 @@
+//! Cache maintenance by virtual address, for memory shared with agents outside the CPU's
+//! coherency domain, like DMA engines or the VideoCore.
+
+use core::ops::Range;
+
+/// Size of the smallest data cache line in bytes, from CTR_EL0.DminLine.
+fn dcache_line_size() -> usize {
+    let ctr: u64;
+    unsafe { asm!("mrs $0, ctr_el0" : "=r"(ctr) ::: "volatile") };
+
+    // DminLine is the log2 of the number of 4-byte words.
+    4 << ((ctr >> 16) & 0xF)
+}
+
+/// Iterate over the start addresses of all cache lines touched by the range.
+fn dcache_lines(range: Range<usize>) -> impl Iterator<Item = usize> {
+    let line_size = dcache_line_size();
+
+    ((range.start & !(line_size - 1))..range.end).step_by(line_size)
+}
+
+/// Write dirty lines of the range back to memory and invalidate them (`dc civac`).
+///
+/// Use before a device reads memory the CPU wrote.
+pub fn clean_invalidate_dcache_range(range: Range<usize>) {
+    for line in dcache_lines(range) {
+        unsafe { asm!("dc civac, $0" :: "r"(line) : "memory" : "volatile") };
+    }
+
+    unsafe { asm!("dsb sy" ::: "memory" : "volatile") };
+}
+
+/// Discard the lines of the range without writing them back (`dc ivac`).
+///
+/// Use before the CPU reads memory a device wrote. Lines only partially covered by the range are
+/// discarded as a whole, so unrelated data sharing them loses any unwritten changes.
+///
+/// # Safety
+///
+/// - Pending CPU writes to the range and to partially covered lines are lost.
+#[allow(dead_code)]
+pub unsafe fn invalidate_dcache_range(range: Range<usize>) {
+    for line in dcache_lines(range) {
+        asm!("dc ivac, $0" :: "r"(line) : "memory" : "volatile");
+    }
+
+    asm!("dsb sy" ::: "memory" : "volatile");
+}
+
+/// Wait until all earlier memory accesses of the calling core have completed (`dsb sy`).
+///
+/// Use between writing a buffer in non-cacheable memory and handing it to a device, and between
+/// the device's completion signal and reading the buffer.
+#[allow(dead_code)]
+pub fn data_sync_barrier() {
+    unsafe { asm!("dsb sy" ::: "memory" : "volatile") };
+}
+
+/// Invalidate the whole instruction cache of the calling core (`ic iallu`).
+///
+/// Use after writing code to memory, once the data cache has been cleaned.
+#[allow(dead_code)]
+pub fn invalidate_icache_all() {
+    unsafe {
+        asm!("ic iallu
+              dsb nsh
+              isb" ::: "memory" : "volatile")
+    };
+}
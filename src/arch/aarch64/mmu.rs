@@ -1,15 +1,22 @@
 use crate::{
+    arch::Mutex,
     bsp, interface,
-    memory::{AccessPermissions, AttributeFields, MemAttributes},
+    memory::{
+        self, AccessPermissions, AddressInfo, AttributeFields, MapError, MappedRange,
+        MemAttributes, TranslationFault,
+    },
 };
-use core::convert;
+use core::{convert, ops::RangeInclusive};
 use cortex_a::{barrier, regs::*};
 use register::register_bitfields;
+use spin::Once;
 
 register_bitfields! {u64,
     STAGE1_TABLE_DESCRIPTOR [
         /// Physical address of the next page table.
-        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        ///
+        /// Tables are aligned to the granule size, so with 64 KiB granules bits [15:12] are 0.
+        NEXT_LEVEL_TABLE_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         TYPE  OFFSET(1) NUMBITS(1) [
             Block = 0,
@@ -31,8 +38,10 @@ register_bitfields! {u64,
             True = 1
         ],
 
-        /// Physical address of the next page table (lvl2) or the page descriptor (lvl3).
-        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        /// Physical address of the page (lvl3).
+        ///
+        /// Pages are aligned to the granule size, so with 64 KiB granules bits [15:12] are 0.
+        OUTPUT_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         /// Access flag.
         AF       OFFSET(10) NUMBITS(1) [
@@ -69,44 +78,66 @@ register_bitfields! {u64,
     ]
 }
 
-const SIXTYFOUR_KIB_SHIFT: usize = 16; //  log2(64 * 1024)
-const FIVETWELVE_MIB_SHIFT: usize = 29; // log2(512 * 1024 * 1024)
+register_bitfields! {u64,
+    /// Result of an `AT` instruction.
+    PAR_EL1 [
+        /// Memory attributes of the output, encoded like a MAIR_EL1 attribute. Valid if F is 0.
+        ATTR OFFSET(56) NUMBITS(8) [],
+
+        /// Output address [47:12]. Valid if F is 0.
+        PA   OFFSET(12) NUMBITS(36) [],
+
+        /// Fault status code. Valid if F is 1.
+        FST  OFFSET(1) NUMBITS(6) [],
+
+        F    OFFSET(0) NUMBITS(1) [
+            Success = 0,
+            Fault = 1
+        ]
+    ]
+}
+
+#[cfg(feature = "granule_4k")]
+mod granule_4k;
+#[cfg(feature = "granule_4k")]
+use granule_4k as granule;
+
+#[cfg(not(feature = "granule_4k"))]
+mod granule_64k;
+#[cfg(not(feature = "granule_4k"))]
+use granule_64k as granule;
+
+/// Shift of the address bits that are not translated, i.e. bits [11:0] of a descriptor's output
+/// address are always zero, independent of the granule.
+const DESCRIPTOR_ADDR_SHIFT: usize = 12;
 
-/// A table descriptor for 64 KiB aperture.
+pub const PAGE_SIZE: usize = 1 << granule::PAGE_SHIFT;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+
+/// Both halves of the address space span 4 GiB. The lower half (TTBR0) is free for user
+/// mappings, the upper half (TTBR1) starts at `bsp::KERNEL_VIRT_OFFSET` and holds the kernel.
+const VIRT_ADDR_SPACE_SHIFT: usize = 32;
+const VIRT_ADDR_SPACE_SIZE: usize = 1 << VIRT_ADDR_SPACE_SHIFT;
+
+/// A table descriptor.
 ///
 /// The output points to the next table.
 #[derive(Copy, Clone)]
 #[repr(transparent)]
 struct TableDescriptor(u64);
 
-/// A page descriptor with 64 KiB aperture.
+/// A page descriptor with the aperture of the translation granule.
 ///
 /// The output points to physical memory.
 #[derive(Copy, Clone)]
 #[repr(transparent)]
 struct PageDescriptor(u64);
 
-/// Big monolithic struct for storing the page tables. Individual levels must be 64 KiB aligned,
-/// hence the "reverse" order of appearance.
-#[repr(C)]
-#[repr(align(65536))]
-struct PageTables<const N: usize> {
-    // Page descriptors, covering 64 KiB windows per entry.
-    lvl3: [[PageDescriptor; 8192]; N],
-    // Table descriptors, covering 512 MiB windows.
-    lvl2: [TableDescriptor; N],
-}
+/// The population of `TABLES` from the virtual memory layout happens exactly once.
+static TABLES_POPULATED: Once<Result<(), &'static str>> = Once::new();
 
-/// Usually evaluates to 1 GiB for RPi3 and 4 GiB for RPi 4.
-const ENTRIES_512_MIB: usize = bsp::addr_space_size() >> FIVETWELVE_MIB_SHIFT;
-
-/// The page tables.
-///
-/// Supposed to land in `.bss`. Therefore, ensure that they boil down to all "0" entries.
-static mut TABLES: PageTables<{ ENTRIES_512_MIB }> = PageTables {
-    lvl3: [[PageDescriptor(0); 8192]; ENTRIES_512_MIB],
-    lvl2: [TableDescriptor(0); ENTRIES_512_MIB],
-};
+/// Serializes runtime modifications of `TABLES`.
+static TABLES_LOCK: Mutex<()> = Mutex::new(());
 
 trait BaseAddr {
     fn base_addr_u64(&self) -> u64;
@@ -125,10 +156,10 @@ impl<T, const N: usize> BaseAddr for [T; N] {
 
 impl convert::From<usize> for TableDescriptor {
     fn from(next_lvl_table_addr: usize) -> Self {
-        let shifted = next_lvl_table_addr >> SIXTYFOUR_KIB_SHIFT;
+        let shifted = next_lvl_table_addr >> DESCRIPTOR_ADDR_SHIFT;
         let val = (STAGE1_TABLE_DESCRIPTOR::VALID::True
             + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
-            + STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64))
+            + STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR.val(shifted as u64))
         .value;
 
         TableDescriptor(val)
@@ -146,6 +177,10 @@ impl convert::From<AttributeFields>
                 STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
             }
+            MemAttributes::NonCacheable => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL_NON_CACHEABLE)
+            }
             MemAttributes::Device => {
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
@@ -171,16 +206,135 @@ impl convert::From<AttributeFields>
 
 impl PageDescriptor {
     fn new(output_addr: usize, attribute_fields: AttributeFields) -> PageDescriptor {
-        let shifted = output_addr >> SIXTYFOUR_KIB_SHIFT;
+        let shifted = output_addr >> DESCRIPTOR_ADDR_SHIFT;
         let val = (STAGE1_PAGE_DESCRIPTOR::VALID::True
             + STAGE1_PAGE_DESCRIPTOR::AF::True
             + attribute_fields.into()
             + STAGE1_PAGE_DESCRIPTOR::TYPE::Table
-            + STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted as u64))
+            + STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted as u64))
         .value;
 
         PageDescriptor(val)
     }
+
+    fn is_valid(&self) -> bool {
+        (self.0 & STAGE1_PAGE_DESCRIPTOR::VALID::True.value) != 0
+    }
+
+    fn output_addr(&self) -> usize {
+        let mask = STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.mask << DESCRIPTOR_ADDR_SHIFT;
+
+        (self.0 & mask) as usize
+    }
+
+    fn attr_indx(&self) -> u64 {
+        (self.0 >> STAGE1_PAGE_DESCRIPTOR::AttrIndx.shift) & STAGE1_PAGE_DESCRIPTOR::AttrIndx.mask
+    }
+
+    fn attribute_fields(&self) -> AttributeFields {
+        AttributeFields {
+            mem_attributes: match self.attr_indx() {
+                mair::DEVICE => MemAttributes::Device,
+                mair::NORMAL_NON_CACHEABLE => MemAttributes::NonCacheable,
+                _ => MemAttributes::CacheableDRAM,
+            },
+            acc_perms: if (self.0 & STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1.value) != 0 {
+                AccessPermissions::ReadOnly
+            } else {
+                AccessPermissions::ReadWrite
+            },
+            execute_never: (self.0 & STAGE1_PAGE_DESCRIPTOR::PXN::True.value) != 0,
+        }
+    }
+
+    /// A valid page that EL1 can both write to and execute from.
+    fn is_writable_and_executable(&self) -> bool {
+        let read_only = (self.0 & STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1.value) != 0;
+        let execute_never = (self.0 & STAGE1_PAGE_DESCRIPTOR::PXN::True.value) != 0;
+
+        self.is_valid() && !read_only && !execute_never
+    }
+}
+
+/// TLB and barrier maintenance needed when live page table entries change.
+mod tlb {
+    /// Make page table writes visible to the table walkers of all cores.
+    #[inline(always)]
+    pub unsafe fn sync_table_writes() {
+        asm!("dsb ishst" ::: "memory" : "volatile");
+    }
+
+    /// Invalidate the translation of one page, for any ASID, on every core of the inner shareable
+    /// domain, and wait for the invalidation to complete everywhere.
+    ///
+    /// The operand holds VA[55:12] in bits [43:0]. The bits above are RES0 or select a TTL hint,
+    /// so the sign extension of upper half addresses is masked off.
+    #[inline(always)]
+    pub unsafe fn invalidate_page(virt_addr: usize) {
+        let operand = (virt_addr >> 12) & ((1 << 44) - 1);
+
+        asm!("tlbi vaae1is, $0" :: "r"(operand) : "memory" : "volatile");
+        asm!("dsb ish" ::: "memory" : "volatile");
+    }
+
+    /// Invalidate all EL1 translations on every core of the inner shareable domain.
+    #[inline(always)]
+    pub unsafe fn invalidate_all() {
+        asm!("tlbi vmalle1is" ::: "memory" : "volatile");
+        asm!("dsb ish" ::: "memory" : "volatile");
+    }
+
+    /// Invalidate all EL1 translations of the calling core only.
+    #[inline(always)]
+    pub unsafe fn invalidate_all_local() {
+        asm!("tlbi vmalle1" ::: "memory" : "volatile");
+        asm!("dsb nsh" ::: "memory" : "volatile");
+    }
+
+    /// Resynchronize the instruction stream with the new translations.
+    #[inline(always)]
+    pub unsafe fn sync_context() {
+        asm!("isb" ::: "memory" : "volatile");
+    }
+}
+
+/// Stage 1 EL1 address translation instructions, as used by loads and stores of the kernel.
+mod at {
+    /// Translate `virt_addr` for a read and return the resulting PAR_EL1.
+    #[inline(always)]
+    pub unsafe fn s1e1r(virt_addr: usize) -> u64 {
+        let par: u64;
+        asm!("at s1e1r, $1
+              isb
+              mrs $0, par_el1"
+             : "=r"(par) : "r"(virt_addr) : "memory" : "volatile");
+        par
+    }
+
+    /// Translate `virt_addr` for a write and return the resulting PAR_EL1.
+    #[inline(always)]
+    pub unsafe fn s1e1w(virt_addr: usize) -> u64 {
+        let par: u64;
+        asm!("at s1e1w, $1
+              isb
+              mrs $0, par_el1"
+             : "=r"(par) : "r"(virt_addr) : "memory" : "volatile");
+        par
+    }
+}
+
+/// Decode the fault status code of a failed translation.
+fn translation_fault(par: u64) -> TranslationFault {
+    let fst = ((par >> PAR_EL1::FST.shift) & PAR_EL1::FST.mask) as u8;
+    let level = fst & 0b11;
+
+    match fst >> 2 {
+        0b0000 => TranslationFault::AddressSize(level),
+        0b0001 => TranslationFault::Translation(level),
+        0b0010 => TranslationFault::AccessFlag(level),
+        0b0011 => TranslationFault::Permission(level),
+        _ => TranslationFault::Other(fst),
+    }
 }
 
 /// Constants for indexing the MAIR_EL1.
@@ -188,14 +342,19 @@ impl PageDescriptor {
 mod mair {
     pub const DEVICE: u64 = 0;
     pub const NORMAL: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 2;
 }
 
 /// Setup function for the MAIR_EL1 register.
 fn set_up_mair() {
     // Define the memory types being mapped.
     MAIR_EL1.write(
-        // Attribute 1 - Cacheable normal DRAM.
-        MAIR_EL1::Attr1_HIGH::Memory_OuterWriteBack_NonTransient_ReadAlloc_WriteAlloc
+        // Attribute 2 - Non-cacheable normal DRAM, shared with DMA capable devices.
+        MAIR_EL1::Attr2_HIGH::Memory_OuterNonCacheable
+            + MAIR_EL1::Attr2_LOW_MEMORY::InnerNonCacheable
+
+            // Attribute 1 - Cacheable normal DRAM.
+            + MAIR_EL1::Attr1_HIGH::Memory_OuterWriteBack_NonTransient_ReadAlloc_WriteAlloc
             + MAIR_EL1::Attr1_LOW_MEMORY::InnerWriteBack_NonTransient_ReadAlloc_WriteAlloc
 
             // Attribute 0 - Device.
@@ -204,40 +363,132 @@ fn set_up_mair() {
     );
 }
 
-/// Iterates over all static page table entries and fills them at once.
+/// Fill the page tables with a coarse boot mapping: the whole physical address space is mapped
+/// linearly into the upper half, DRAM as cacheable RWX and the MMIO window as device memory.
+///
+/// Runs with the MMU off, from physical addresses. It must therefore not use anything that
+/// involves absolute addresses, like the function pointers of the BSP's virtual memory layout.
+///
+/// # Safety
+///
+/// - Modifies a `static mut`. Must only be called by the boot core, before any other core runs.
+unsafe fn populate_boot_pt_entries() {
+    granule::populate_table_descriptors();
+
+    for phys_addr in (0..bsp::addr_space_size()).step_by(PAGE_SIZE) {
+        let is_device = bsp::phys_mmio_range().contains(&phys_addr);
+        let attribute_fields = AttributeFields {
+            mem_attributes: if is_device {
+                MemAttributes::Device
+            } else {
+                MemAttributes::CacheableDRAM
+            },
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: is_device,
+        };
+
+        *granule::page_descriptor(memory::kernel_phys_to_virt(phys_addr)) =
+            PageDescriptor::new(phys_addr, attribute_fields);
+    }
+}
+
+/// Iterates over all page table entries and refines the boot mapping according to the BSP's
+/// virtual memory layout.
+///
+/// The boot mapping already uses the same output addresses, so most entries only change their
+/// permissions or become invalid, neither of which requires break-before-make. Entries that change
+/// their memory type, like non-cacheable DMA memory, are replaced with break-before-make. The TLBs
+/// are flushed at the end.
 ///
 /// # Safety
 ///
 /// - Modifies a `static mut`. Ensure it only happens from here.
 unsafe fn populate_pt_entries() -> Result<(), &'static str> {
-    for (l2_nr, l2_entry) in TABLES.lvl2.iter_mut().enumerate() {
-        *l2_entry = TABLES.lvl3[l2_nr].base_addr_usize().into();
+    for virt_addr in address_space_pages() {
+        let desc = match bsp::virt_mem_layout().get_virtual_addr_properties(virt_addr)? {
+            Some((output_addr, attribute_fields)) => {
+                PageDescriptor::new(output_addr, attribute_fields)
+            }
+            None => PageDescriptor(0),
+        };
 
-        for (l3_nr, l3_entry) in TABLES.lvl3[l2_nr].iter_mut().enumerate() {
-            let virt_addr = (l2_nr << FIVETWELVE_MIB_SHIFT) + (l3_nr << SIXTYFOUR_KIB_SHIFT);
+        let current = *granule::page_descriptor(virt_addr);
+        if desc.is_valid() && current.is_valid() && desc.attr_indx() != current.attr_indx() {
+            replace_page_descriptor(virt_addr, desc);
+        } else {
+            *granule::page_descriptor(virt_addr) = desc;
+        }
+    }
 
-            let (output_addr, attribute_fields) =
-                bsp::virt_mem_layout().get_virtual_addr_properties(virt_addr)?;
+    tlb::sync_table_writes();
+    tlb::invalidate_all();
+    tlb::sync_context();
 
-            *l3_entry = PageDescriptor::new(output_addr, attribute_fields);
-        }
+    Ok(())
+}
+
+/// Check that the range is page aligned and covered by `TABLES`.
+fn check_range(virt_range: &RangeInclusive<usize>) -> Result<(), MapError> {
+    if virt_range.is_empty() {
+        return Err(MapError::EmptyRange);
+    }
+
+    if (*virt_range.start() & PAGE_MASK) != 0 || (*virt_range.end() & PAGE_MASK) != PAGE_MASK {
+        return Err(MapError::Misaligned);
+    }
+
+    if *virt_range.start() < bsp::KERNEL_VIRT_OFFSET
+        || *virt_range.end() > bsp::KERNEL_VIRT_OFFSET + (bsp::addr_space_size() - 1)
+    {
+        return Err(MapError::OutOfRange);
     }
 
     Ok(())
 }
 
+/// Iterate over the start addresses of all pages of the kernel's address space.
+fn address_space_pages() -> impl Iterator<Item = usize> {
+    pages(&(bsp::KERNEL_VIRT_OFFSET..=bsp::KERNEL_VIRT_OFFSET + (bsp::addr_space_size() - 1)))
+}
+
+/// Iterate over the start addresses of all pages in the range.
+fn pages(virt_range: &RangeInclusive<usize>) -> impl Iterator<Item = usize> {
+    (*virt_range.start()..=*virt_range.end()).step_by(PAGE_SIZE)
+}
+
+/// Replace a live page descriptor, following the break-before-make sequence required by the
+/// architecture: invalidate the entry, flush it from all TLBs and only then write the new one.
+unsafe fn replace_page_descriptor(virt_addr: usize, new: PageDescriptor) {
+    *granule::page_descriptor(virt_addr) = PageDescriptor(0);
+    tlb::sync_table_writes();
+    tlb::invalidate_page(virt_addr);
+
+    *granule::page_descriptor(virt_addr) = new;
+    tlb::sync_table_writes();
+}
+
 /// Configure various settings of stage 1 of the EL1 translation regime.
+///
+/// Both halves start out enabled, because the core is still executing from physical addresses
+/// through the lower half when the MMU is switched on.
 fn configure_translation_control() {
     let ips = ID_AA64MMFR0_EL1.read(ID_AA64MMFR0_EL1::PARange);
+    let txsz = (64 - VIRT_ADDR_SPACE_SHIFT) as u64;
+
     TCR_EL1.write(
         TCR_EL1::TBI0::Ignored
             + TCR_EL1::IPS.val(ips)
-            + TCR_EL1::TG0::KiB_64
+            + granule::tcr_granule_fields()
             + TCR_EL1::SH0::Inner
             + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
             + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
             + TCR_EL1::EPD0::EnableTTBR0Walks
-            + TCR_EL1::T0SZ.val(32), // TTBR0 spans 4 GiB total.
+            + TCR_EL1::T0SZ.val(txsz)
+            + TCR_EL1::SH1::Inner
+            + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::EPD1::EnableTTBR1Walks
+            + TCR_EL1::T1SZ.val(txsz),
     );
 }
 
@@ -247,25 +498,33 @@ fn configure_translation_control() {
 
 pub struct MMU;
 
+/// Prepare the boot mapping. See `populate_boot_pt_entries()`.
+///
+/// # Safety
+///
+/// - Must only be called by the boot core, with the MMU off, before any other core runs.
+pub unsafe fn populate_boot_page_tables() {
+    populate_boot_pt_entries();
+}
+
 //--------------------------------------------------------------------------------------------------
 // OS interface implementations
 //--------------------------------------------------------------------------------------------------
 
 impl interface::mm::MMU for MMU {
-    unsafe fn init(&self) -> Result<(), &'static str> {
+    unsafe fn enable(&self) -> Result<(), &'static str> {
         // Fail early if translation granule is not supported. Both RPis support it, though.
-        if !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported) {
-            return Err("64 KiB translation granule not supported");
-        }
+        granule::check_supported()?;
 
         // Prepare the memory attribute indirection register.
         set_up_mair();
 
-        // Populate page tables.
-        populate_pt_entries()?;
-
-        // Set the "Translation Table Base Register".
-        TTBR0_EL1.set_baddr(TABLES.lvl2.base_addr_u64());
+        // Until the core has left its physical addresses, the lower half maps the same tables as
+        // the upper half. Both only look at the address bits below the 4 GiB boundary, so this is
+        // an identity mapping there.
+        let tables_phys_addr = memory::kernel_virt_to_phys(granule::base_addr_u64() as usize);
+        TTBR0_EL1.set_baddr(tables_phys_addr as u64);
+        TTBR1_EL1.set_baddr(tables_phys_addr as u64);
 
         configure_translation_control();
 
@@ -282,4 +541,168 @@ impl interface::mm::MMU for MMU {
 
         Ok(())
     }
+
+    unsafe fn release_lower_half(&self) {
+        TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+        tlb::sync_context();
+
+        tlb::invalidate_all_local();
+        tlb::sync_context();
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        // Populate page tables.
+        *TABLES_POPULATED.call_once(|| populate_pt_entries())
+    }
+
+    fn find_writable_executable_page(&self) -> Option<usize> {
+        let _lock = TABLES_LOCK.lock();
+
+        address_space_pages().find(|&virt_addr| unsafe {
+            granule::page_descriptor(virt_addr).is_writable_and_executable()
+        })
+    }
+
+    fn translate(&self, virt_addr: usize) -> Result<AddressInfo, TranslationFault> {
+        let par = unsafe { at::s1e1r(virt_addr) };
+        if (par & PAR_EL1::F::Fault.value) != 0 {
+            return Err(translation_fault(par));
+        }
+
+        // The PA field holds bits [47:12], independent of the granule.
+        let pa_mask = PAR_EL1::PA.mask << PAR_EL1::PA.shift;
+        let phys_addr = (par & pa_mask) as usize | (virt_addr & ((1 << DESCRIPTOR_ADDR_SHIFT) - 1));
+
+        // Device memory types have an outer attribute of 0b0000, normal non-cacheable memory is
+        // 0b0100 both inner and outer.
+        let attr = (par >> PAR_EL1::ATTR.shift) & PAR_EL1::ATTR.mask;
+        let mem_attributes = match attr {
+            0x00..=0x0F => MemAttributes::Device,
+            0x44 => MemAttributes::NonCacheable,
+            _ => MemAttributes::CacheableDRAM,
+        };
+
+        let acc_perms = if (unsafe { at::s1e1w(virt_addr) } & PAR_EL1::F::Fault.value) != 0 {
+            AccessPermissions::ReadOnly
+        } else {
+            AccessPermissions::ReadWrite
+        };
+
+        Ok(AddressInfo {
+            virt_addr,
+            phys_addr,
+            mem_attributes,
+            acc_perms,
+        })
+    }
+
+    fn for_each_mapped_range(&self, f: &mut dyn FnMut(&MappedRange)) {
+        let _lock = TABLES_LOCK.lock();
+
+        let mapped_range = |start: usize, end: usize, first: PageDescriptor| MappedRange {
+            virt_range: start..=end,
+            phys_start: first.output_addr(),
+            attribute_fields: first.attribute_fields(),
+        };
+
+        // Start address and first descriptor of the current run, and the run's last descriptor.
+        // A page continues the run if its descriptor only differs in the next output address.
+        let mut run: Option<(usize, PageDescriptor, PageDescriptor)> = None;
+
+        for virt_addr in address_space_pages() {
+            let desc = unsafe { *granule::page_descriptor(virt_addr) };
+
+            if let Some((start, first, last)) = run {
+                if desc.is_valid() && desc.0 == last.0 + PAGE_SIZE as u64 {
+                    run = Some((start, first, desc));
+                    continue;
+                }
+
+                f(&mapped_range(start, virt_addr - 1, first));
+                run = None;
+            }
+
+            if desc.is_valid() {
+                run = Some((virt_addr, desc, desc));
+            }
+        }
+
+        if let Some((start, first, _)) = run {
+            let end = bsp::KERNEL_VIRT_OFFSET + (bsp::addr_space_size() - 1);
+            f(&mapped_range(start, end, first));
+        }
+    }
+
+    unsafe fn map(
+        &self,
+        virt_range: RangeInclusive<usize>,
+        phys_addr: usize,
+        attribute_fields: AttributeFields,
+    ) -> Result<(), MapError> {
+        check_range(&virt_range)?;
+        if (phys_addr & PAGE_MASK) != 0 {
+            return Err(MapError::Misaligned);
+        }
+
+        let _lock = TABLES_LOCK.lock();
+
+        if pages(&virt_range).any(|virt_addr| granule::page_descriptor(virt_addr).is_valid()) {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        // The entries are invalid, so no TLB can hold them and no break-before-make is needed.
+        for virt_addr in pages(&virt_range) {
+            let output_addr = phys_addr + (virt_addr - virt_range.start());
+            *granule::page_descriptor(virt_addr) =
+                PageDescriptor::new(output_addr, attribute_fields);
+        }
+        tlb::sync_table_writes();
+        tlb::sync_context();
+
+        Ok(())
+    }
+
+    unsafe fn unmap(&self, virt_range: RangeInclusive<usize>) -> Result<(), MapError> {
+        check_range(&virt_range)?;
+
+        let _lock = TABLES_LOCK.lock();
+
+        if !pages(&virt_range).all(|virt_addr| granule::page_descriptor(virt_addr).is_valid()) {
+            return Err(MapError::NotMapped);
+        }
+
+        for virt_addr in pages(&virt_range) {
+            *granule::page_descriptor(virt_addr) = PageDescriptor(0);
+            tlb::sync_table_writes();
+            tlb::invalidate_page(virt_addr);
+        }
+        tlb::sync_context();
+
+        Ok(())
+    }
+
+    unsafe fn protect(
+        &self,
+        virt_range: RangeInclusive<usize>,
+        attribute_fields: AttributeFields,
+    ) -> Result<(), MapError> {
+        check_range(&virt_range)?;
+
+        let _lock = TABLES_LOCK.lock();
+
+        if !pages(&virt_range).all(|virt_addr| granule::page_descriptor(virt_addr).is_valid()) {
+            return Err(MapError::NotMapped);
+        }
+
+        for virt_addr in pages(&virt_range) {
+            let output_addr = granule::page_descriptor(virt_addr).output_addr();
+            replace_page_descriptor(
+                virt_addr,
+                PageDescriptor::new(output_addr, attribute_fields),
+            );
+        }
+        tlb::sync_context();
+
+        Ok(())
+    }
 }
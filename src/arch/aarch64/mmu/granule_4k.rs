@@ -0,0 +1,92 @@
+//! Three-level translation tables with a 4 KiB granule.
+//!
+//! lvl1 entries cover 1 GiB, lvl2 entries cover 2 MiB, lvl3 entries cover 4 KiB.
+
+use super::{BaseAddr, PageDescriptor, TableDescriptor, VIRT_ADDR_SPACE_SIZE};
+use crate::{bsp, memory};
+use cortex_a::regs::*;
+use register::FieldValue;
+
+pub(super) const PAGE_SHIFT: usize = 12; // log2(4 * 1024)
+const TWO_MIB_SHIFT: usize = 21; // log2(2 * 1024 * 1024)
+const ONE_GIB_SHIFT: usize = 30; // log2(1024 * 1024 * 1024)
+
+const ENTRIES_PER_TABLE: usize = 512;
+
+/// The granule fields of TCR_EL1 for both halves.
+///
+/// TG1 is encoded differently than TG0, with 0b10 standing for 4 KiB.
+pub(super) fn tcr_granule_fields() -> FieldValue<u64, TCR_EL1::Register> {
+    TCR_EL1::TG0::KiB_4 + TCR_EL1::TG1.val(0b10)
+}
+
+/// Big monolithic struct for storing the page tables. Every table is exactly 4 KiB, so aligning
+/// the struct keeps all of them 4 KiB aligned.
+#[repr(C)]
+#[repr(align(4096))]
+struct PageTables<const N: usize> {
+    // Page descriptors, covering 4 KiB windows per entry.
+    lvl3: [[[PageDescriptor; ENTRIES_PER_TABLE]; ENTRIES_PER_TABLE]; N],
+    // Table descriptors, covering 2 MiB windows.
+    lvl2: [[TableDescriptor; ENTRIES_PER_TABLE]; N],
+    // Table descriptors, covering 1 GiB windows. With a 4 GiB half, the walk starts here and only
+    // the first four entries are reachable. Entries beyond `N` stay invalid and fault.
+    lvl1: [TableDescriptor; ENTRIES_PER_TABLE],
+}
+
+/// Usually evaluates to 1 for RPi3 and 4 for RPi 4.
+const ENTRIES_1_GIB: usize = bsp::addr_space_size() >> ONE_GIB_SHIFT;
+
+/// The page tables.
+///
+/// Supposed to land in `.bss`. Therefore, ensure that they boil down to all "0" entries.
+static mut TABLES: PageTables<{ ENTRIES_1_GIB }> = PageTables {
+    lvl3: [[[PageDescriptor(0); ENTRIES_PER_TABLE]; ENTRIES_PER_TABLE]; ENTRIES_1_GIB],
+    lvl2: [[TableDescriptor(0); ENTRIES_PER_TABLE]; ENTRIES_1_GIB],
+    lvl1: [TableDescriptor(0); ENTRIES_PER_TABLE],
+};
+
+pub(super) fn check_supported() -> Result<(), &'static str> {
+    if !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran4::Supported) {
+        return Err("4 KiB translation granule not supported");
+    }
+
+    Ok(())
+}
+
+/// Point each used lvl1 entry to its lvl2 table, and each lvl2 entry to its lvl3 table.
+///
+/// # Safety
+///
+/// - Modifies a `static mut`.
+/// - May run with the MMU off, so the table addresses are converted to physical ones explicitly.
+pub(super) unsafe fn populate_table_descriptors() {
+    for (l1_nr, l1_entry) in TABLES.lvl1.iter_mut().take(ENTRIES_1_GIB).enumerate() {
+        *l1_entry = memory::kernel_virt_to_phys(TABLES.lvl2[l1_nr].base_addr_usize()).into();
+
+        for (l2_nr, l2_entry) in TABLES.lvl2[l1_nr].iter_mut().enumerate() {
+            *l2_entry =
+                memory::kernel_virt_to_phys(TABLES.lvl3[l1_nr][l2_nr].base_addr_usize()).into();
+        }
+    }
+}
+
+/// Return the page descriptor that translates `virt_addr`. Only the offset into the 4 GiB half is
+/// used, so this works for upper half addresses as well as for physical ones.
+///
+/// # Safety
+///
+/// - Hands out a mutable reference into a `static mut`. Callers must serialize access.
+pub(super) unsafe fn page_descriptor(virt_addr: usize) -> &'static mut PageDescriptor {
+    let virt_addr = virt_addr & (VIRT_ADDR_SPACE_SIZE - 1);
+    let l1_nr = virt_addr >> ONE_GIB_SHIFT;
+    let l2_nr = (virt_addr >> TWO_MIB_SHIFT) & (ENTRIES_PER_TABLE - 1);
+    let l3_nr = (virt_addr >> PAGE_SHIFT) & (ENTRIES_PER_TABLE - 1);
+
+    &mut TABLES.lvl3[l1_nr][l2_nr][l3_nr]
+}
+
+/// The address of the table the walk starts at.
+pub(super) unsafe fn base_addr_u64() -> u64 {
+    TABLES.lvl1.base_addr_u64()
+}
@@ -0,0 +1,84 @@
+//! Two-level translation tables with a 64 KiB granule.
+//!
+//! lvl2 entries cover 512 MiB, lvl3 entries cover 64 KiB.
+
+use super::{BaseAddr, PageDescriptor, TableDescriptor, VIRT_ADDR_SPACE_SIZE};
+use crate::{bsp, memory};
+use cortex_a::regs::*;
+use register::FieldValue;
+
+pub(super) const PAGE_SHIFT: usize = 16; //  log2(64 * 1024)
+const FIVETWELVE_MIB_SHIFT: usize = 29; // log2(512 * 1024 * 1024)
+
+const ENTRIES_PER_TABLE: usize = 8192;
+
+/// Number of lvl2 entries needed to span one half of the virtual address space.
+const LVL2_ENTRIES: usize = VIRT_ADDR_SPACE_SIZE >> FIVETWELVE_MIB_SHIFT;
+
+/// The granule fields of TCR_EL1 for both halves.
+///
+/// TG1 is encoded differently than TG0, with 0b11 standing for 64 KiB.
+pub(super) fn tcr_granule_fields() -> FieldValue<u64, TCR_EL1::Register> {
+    TCR_EL1::TG0::KiB_64 + TCR_EL1::TG1.val(0b11)
+}
+
+/// Big monolithic struct for storing the page tables. Individual levels must be 64 KiB aligned,
+/// hence the "reverse" order of appearance.
+#[repr(C)]
+#[repr(align(65536))]
+struct PageTables<const N: usize> {
+    // Page descriptors, covering 64 KiB windows per entry.
+    lvl3: [[PageDescriptor; ENTRIES_PER_TABLE]; N],
+    // Table descriptors, covering 512 MiB windows. Entries beyond `N` stay invalid and fault.
+    lvl2: [TableDescriptor; LVL2_ENTRIES],
+}
+
+/// Usually evaluates to 1 GiB for RPi3 and 4 GiB for RPi 4.
+const ENTRIES_512_MIB: usize = bsp::addr_space_size() >> FIVETWELVE_MIB_SHIFT;
+
+/// The page tables.
+///
+/// Supposed to land in `.bss`. Therefore, ensure that they boil down to all "0" entries.
+static mut TABLES: PageTables<{ ENTRIES_512_MIB }> = PageTables {
+    lvl3: [[PageDescriptor(0); ENTRIES_PER_TABLE]; ENTRIES_512_MIB],
+    lvl2: [TableDescriptor(0); LVL2_ENTRIES],
+};
+
+pub(super) fn check_supported() -> Result<(), &'static str> {
+    if !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported) {
+        return Err("64 KiB translation granule not supported");
+    }
+
+    Ok(())
+}
+
+/// Point each used lvl2 entry to its lvl3 table.
+///
+/// # Safety
+///
+/// - Modifies a `static mut`.
+/// - May run with the MMU off, so the table addresses are converted to physical ones explicitly.
+pub(super) unsafe fn populate_table_descriptors() {
+    for (l2_nr, l2_entry) in TABLES.lvl2.iter_mut().take(ENTRIES_512_MIB).enumerate() {
+        *l2_entry = memory::kernel_virt_to_phys(TABLES.lvl3[l2_nr].base_addr_usize()).into();
+    }
+}
+
+/// Return the page descriptor that translates `virt_addr`. Only the offset into the 4 GiB half is
+/// used, so this works for upper half addresses as well as for physical ones.
+///
+/// # Safety
+///
+/// - Hands out a mutable reference into a `static mut`. Callers must serialize access.
+pub(super) unsafe fn page_descriptor(virt_addr: usize) -> &'static mut PageDescriptor {
+    let virt_addr = virt_addr & (VIRT_ADDR_SPACE_SIZE - 1);
+    let l2_nr = virt_addr >> FIVETWELVE_MIB_SHIFT;
+    let l3_nr = (virt_addr >> PAGE_SHIFT) & (ENTRIES_PER_TABLE - 1);
+
+    &mut TABLES.lvl3[l2_nr][l3_nr]
+}
+
+/// The address of the table the walk starts at.
+pub(super) unsafe fn base_addr_u64() -> u64 {
+    TABLES.lvl2.base_addr_u64()
+}
@@ -0,0 +1,104 @@
+//! Power State Coordination Interface (PSCI) calls, through which firmware or a hypervisor powers
+//! cores on and off. Used instead of spin tables where the platform provides it.
+
+use core::fmt;
+
+/// CPU_ON, SMC64 calling convention.
+const CPU_ON: u64 = 0xC400_0003;
+
+/// The instruction that traps into the PSCI implementation, from the device tree's `/psci`
+/// `method` property.
+#[derive(Copy, Clone)]
+pub enum Conduit {
+    Hvc,
+    Smc,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum PsciError {
+    NotSupported,
+    InvalidParameters,
+    Denied,
+    AlreadyOn,
+    OnPending,
+    InternalFailure,
+    NotPresent,
+    Disabled,
+    InvalidAddress,
+    Unknown(i64),
+}
+
+impl From<i64> for PsciError {
+    fn from(code: i64) -> Self {
+        match code {
+            -1 => PsciError::NotSupported,
+            -2 => PsciError::InvalidParameters,
+            -3 => PsciError::Denied,
+            -4 => PsciError::AlreadyOn,
+            -5 => PsciError::OnPending,
+            -6 => PsciError::InternalFailure,
+            -7 => PsciError::NotPresent,
+            -8 => PsciError::Disabled,
+            -9 => PsciError::InvalidAddress,
+            _ => PsciError::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for PsciError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsciError::NotSupported => write!(f, "Not supported"),
+            PsciError::InvalidParameters => write!(f, "Invalid parameters"),
+            PsciError::Denied => write!(f, "Denied"),
+            PsciError::AlreadyOn => write!(f, "Already on"),
+            PsciError::OnPending => write!(f, "On pending"),
+            PsciError::InternalFailure => write!(f, "Internal failure"),
+            PsciError::NotPresent => write!(f, "Not present"),
+            PsciError::Disabled => write!(f, "Disabled"),
+            PsciError::InvalidAddress => write!(f, "Invalid address"),
+            PsciError::Unknown(code) => write!(f, "Unknown error {}", code),
+        }
+    }
+}
+
+/// Issue a PSCI call with up to three arguments and return x0.
+///
+/// The SMC calling convention lets the callee corrupt x0 to x17.
+unsafe fn call(conduit: Conduit, function: u64, arg0: u64, arg1: u64, arg2: u64) -> i64 {
+    let mut x0 = function;
+    let mut x1 = arg0;
+    let mut x2 = arg1;
+    let mut x3 = arg2;
+
+    match conduit {
+        Conduit::Hvc => asm!("hvc #0"
+            : "+{x0}"(x0), "+{x1}"(x1), "+{x2}"(x2), "+{x3}"(x3)
+            :
+            : "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14", "x15",
+              "x16", "x17", "memory"
+            : "volatile"),
+        Conduit::Smc => asm!("smc #0"
+            : "+{x0}"(x0), "+{x1}"(x1), "+{x2}"(x2), "+{x3}"(x3)
+            :
+            : "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14", "x15",
+              "x16", "x17", "memory"
+            : "volatile"),
+    }
+
+    x0 as i64
+}
+
+/// Power on the core with the affinity `target_mpidr`. It starts at the physical `entry_point`
+/// with its MMU off and `context_id` in x0.
+pub unsafe fn cpu_on(
+    conduit: Conduit,
+    target_mpidr: u64,
+    entry_point: u64,
+    context_id: u64,
+) -> Result<(), PsciError> {
+    match call(conduit, CPU_ON, target_mpidr, entry_point, context_id) {
+        0 => Ok(()),
+        code => Err(code.into()),
+    }
+}
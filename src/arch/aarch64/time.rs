@@ -1,6 +1,7 @@
-use crate::{interface, warn};
+use crate::{interface, interface::time::Timer as _, warn};
 use core::time::Duration;
 use cortex_a::regs::*;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 
 const NS_PER_S: u64 = 1_000_000_000;
 
@@ -10,6 +11,11 @@ const NS_PER_S: u64 = 1_000_000_000;
 
 pub struct Timer;
 
+/// `embedded-hal` delays, spinning on the timer.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Default)]
+pub struct Delay;
+
 //--------------------------------------------------------------------------------------------------
 // OS interface implementations
 //--------------------------------------------------------------------------------------------------
@@ -120,3 +126,43 @@ pub fn sleep(duration: Duration) {
     // Disable counting again.
     CNTP_CTL_EL0.modify(CNTP_CTL_EL0::ENABLE::CLEAR);
 }
+
+//--------------------------------------------------------------------------------------------------
+// embedded-hal implementations
+//--------------------------------------------------------------------------------------------------
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        Timer.spin_for(Duration::from_millis(ms.into()));
+    }
+}
+
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(u32::from(ms));
+    }
+}
+
+impl DelayMs<u8> for Delay {
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay_ms(u32::from(ms));
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        Timer.spin_for(Duration::from_micros(us.into()));
+    }
+}
+
+impl DelayUs<u16> for Delay {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(u32::from(us));
+    }
+}
+
+impl DelayUs<u8> for Delay {
+    fn delay_us(&mut self, us: u8) {
+        self.delay_us(u32::from(us));
+    }
+}
@@ -1,4 +1,18 @@
 pub mod driver;
+
+mod common;
+
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 mod rpi;
 
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 pub use rpi::*;
+
+#[cfg(feature = "bsp_qemu_virt")]
+mod qemu_virt;
+
+#[cfg(feature = "bsp_qemu_virt")]
+pub use qemu_virt::*;
+
+#[cfg(not(any(feature = "bsp_rpi3", feature = "bsp_rpi4", feature = "bsp_qemu_virt")))]
+compile_error!("Select a board with one of the bsp_* features, e.g. `make BSP=rpi3`.");
@@ -0,0 +1,114 @@
+//! Helpers shared by the BSPs.
+
+use crate::memory::phys_map::{self, PhysMemoryMap, PhysMemorySource};
+use crate::{arch, driver, fdt, memory};
+use core::ops::RangeInclusive;
+
+/// The first enabled node compatible with `compatible`, or else the first disabled one: the
+/// firmware disables some devices the kernel drives anyway, like the PWM of the RPis.
+pub fn find_device(
+    tree: &'static fdt::DeviceTree<'static>,
+    compatible: &str,
+) -> Option<fdt::Node<'static>> {
+    tree.find_compatible(compatible)
+        .find(fdt::Node::is_enabled)
+        .or_else(|| tree.find_compatible(compatible).next())
+}
+
+/// The node compatible with `compatible` whose first `reg` entry starts at `phys_addr`.
+pub fn find_device_at(
+    tree: &'static fdt::DeviceTree<'static>,
+    compatible: &str,
+    phys_addr: usize,
+) -> Option<fdt::Node<'static>> {
+    tree.find_compatible(compatible).find(|node| {
+        node.reg()
+            .next()
+            .map_or(false, |(addr, _)| addr as usize == phys_addr)
+    })
+}
+
+/// Match each registered driver to a node by its `compatible()`, and by its
+/// `fixed_phys_base_addr()` if it has one. Record the node in the driver's descriptor and point
+/// the driver at the node's first `reg` entry.
+///
+/// # Safety
+///
+/// - Must be called before the drivers are initialized.
+pub unsafe fn probe_device_drivers(tree: &'static fdt::DeviceTree<'static>) {
+    for descriptor in driver::driver_manager_mut().descriptors_mut() {
+        let driver = descriptor.driver();
+        let node = match driver.fixed_phys_base_addr() {
+            Some(phys_addr) => find_device_at(tree, driver.compatible(), phys_addr),
+            None => find_device(tree, driver.compatible()),
+        };
+
+        if let Some((addr, _)) = node.and_then(|node| node.reg().next()) {
+            descriptor.driver_mut().set_phys_base_addr(addr as usize);
+        }
+        descriptor.set_node(node);
+    }
+}
+
+/// Describe the physical memory from the firmware's device tree, or with `default_ram` if it does
+/// not list any RAM.
+///
+/// # Panics
+///
+/// - If the `dma` region or `heap_start`, both virtual, are not in free RAM.
+pub fn init_phys_memory_map(
+    default_ram: RangeInclusive<usize>,
+    dma: RangeInclusive<usize>,
+    heap_start: usize,
+) {
+    let mut map = PhysMemoryMap::new(PhysMemorySource::DeviceTree);
+
+    if let Ok(tree) = fdt::device_tree() {
+        tree.for_each_memory_region(&mut |addr, size| map.add_ram(addr as usize, size as usize));
+        tree.for_each_reserved_region(&mut |addr, size| {
+            map.add_reserved(addr as usize, size as usize)
+        });
+    }
+
+    if !map.has_ram() {
+        map = PhysMemoryMap::new(PhysMemorySource::Default);
+        map.add_ram(
+            *default_ram.start(),
+            default_ram.end() - default_ram.start() + 1,
+        );
+    }
+
+    let dma_start = memory::kernel_virt_to_phys(*dma.start());
+    let dma_end = memory::kernel_virt_to_phys(*dma.end());
+    if map
+        .free_end_inclusive(dma_start)
+        .map_or(true, |end| end < dma_end)
+    {
+        panic!("DMA region is not in free RAM:\n{}", map);
+    }
+
+    if map
+        .free_end_inclusive(memory::kernel_virt_to_phys(heap_start))
+        .is_none()
+    {
+        panic!("Kernel heap start is not in free RAM:\n{}", map);
+    }
+
+    phys_map::init(map);
+}
+
+/// The kernel heap: from the virtual `heap_start` to the end of the free RAM it lies in, in whole
+/// pages and not beyond the physical address `phys_limit_inclusive`.
+pub fn kernel_heap_range(heap_start: usize, phys_limit_inclusive: usize) -> RangeInclusive<usize> {
+    let phys_end = phys_map::phys_memory_map()
+        .free_end_inclusive(memory::kernel_virt_to_phys(heap_start))
+        .unwrap_or(0)
+        .min(phys_limit_inclusive);
+    let phys_end_exclusive = (phys_end + 1) & !(arch::PAGE_SIZE - 1);
+
+    #[allow(clippy::range_minus_one)]
+    RangeInclusive::new(
+        heap_start,
+        memory::kernel_phys_to_virt(phys_end_exclusive) - 1,
+    )
+}
@@ -1,9 +1,39 @@
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+mod bsc;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 mod clock;
-mod gpio;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+mod dma;
+#[cfg(any(feature = "bsp_rpi4", feature = "bsp_qemu_virt"))]
+mod gicv2;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod gpio;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub mod mailbox;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+mod mini_uart;
 mod pl011_uart;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 mod pwm;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+mod spi;
 
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub use bsc::BSC;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 pub use clock::Clock;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub use dma::DMA;
+#[cfg(any(feature = "bsp_rpi4", feature = "bsp_qemu_virt"))]
+pub use gicv2::GICv2;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 pub use gpio::GPIO;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub use mailbox::Mailbox;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub use mini_uart::{MiniUart, PanicMiniUart};
 pub use pl011_uart::{PL011Uart, PanicUart};
-pub use pwm::PWM;
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub use pwm::{PwmChannel, PWM};
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+pub use spi::SPI;
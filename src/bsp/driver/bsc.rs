@@ -0,0 +1,403 @@
+//! Driver for the Broadcom Serial Controller (BSC), the BCM2837's I2C master.
+//!
+//! Transfers are polled through the 16 byte FIFO. The controller waits for devices that stretch
+//! the clock by itself, up to the timeout in `CLKT`. Its bus clock is divided from the VPU core
+//! clock.
+
+use crate::bsp::driver::gpio;
+use crate::{
+    arch,
+    arch::Mutex,
+    interface,
+    interface::{
+        i2c::{Error, Result},
+        time::Timer,
+    },
+    memory::{
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use core::{cmp, mem, ops, time::Duration};
+use register::{mmio::ReadWrite, register_bitfields, register_structs, FieldValue};
+
+register_bitfields! {
+    u32,
+
+    /// Control
+    C [
+        I2CEN OFFSET(15) NUMBITS(1) [],
+        ST OFFSET(7) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            Fifo = 0b11
+        ],
+        READ OFFSET(0) NUMBITS(1) [
+            Write = 0,
+            Read = 1
+        ]
+    ],
+
+    /// Status. CLKT, ERR and DONE are cleared by writing 1.
+    S [
+        CLKT OFFSET(9) NUMBITS(1) [],
+        ERR OFFSET(8) NUMBITS(1) [],
+        RXD OFFSET(5) NUMBITS(1) [],
+        TXD OFFSET(4) NUMBITS(1) [],
+        DONE OFFSET(1) NUMBITS(1) [],
+        TA OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Data Length
+    DLEN [
+        DLEN OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// Slave Address
+    A [
+        ADDR OFFSET(0) NUMBITS(7) []
+    ],
+
+    /// Clock Divider
+    DIV [
+        CDIV OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// Data Delay
+    DEL [
+        FEDL OFFSET(16) NUMBITS(16) [],
+        REDL OFFSET(0) NUMBITS(16) []
+    ],
+
+    /// Clock Stretch Timeout
+    CLKT [
+        TOUT OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => C: ReadWrite<u32, C::Register>),
+        (0x04 => S: ReadWrite<u32, S::Register>),
+        (0x08 => DLEN: ReadWrite<u32, DLEN::Register>),
+        (0x0C => A: ReadWrite<u32, A::Register>),
+        (0x10 => FIFO: ReadWrite<u32>),
+        (0x14 => DIV: ReadWrite<u32, DIV::Register>),
+        (0x18 => DEL: ReadWrite<u32, DEL::Register>),
+        (0x1C => CLKT: ReadWrite<u32, CLKT::Register>),
+        (0x20 => @END),
+    }
+}
+
+/// Depth of the TX and RX FIFOs.
+const FIFO_SIZE: usize = 16;
+
+/// Longest transfer `DLEN` can describe.
+const MAX_TRANSFER_LEN: usize = 0xFFFF;
+
+/// Standard mode.
+const DEFAULT_SPEED_HZ: u32 = 100_000;
+
+/// How long a device may hold the clock low, SMBus' limit.
+const CLOCK_STRETCH_TIMEOUT_MS: u32 = 35;
+
+struct BSCInner {
+    phys_base_addr: usize,
+    core_clock_hz: u32,
+    speed_hz: u32,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
+}
+
+impl ops::Deref for BSCInner {
+    type Target = RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr() }
+    }
+}
+
+impl BSCInner {
+    const fn new(phys_base_addr: usize, core_clock_hz: u32) -> BSCInner {
+        BSCInner {
+            phys_base_addr,
+            core_clock_hz,
+            speed_hz: DEFAULT_SPEED_HZ,
+            mmio: None,
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> core::result::Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
+    }
+
+    fn ptr(&self) -> *const RegisterBlock {
+        self.mmio
+            .as_ref()
+            .expect("BSC registers are not mapped")
+            .ptr()
+    }
+
+    /// Program the divider, data delays and clock stretch timeout for `speed_hz`.
+    fn apply_speed(&self) {
+        let cdiv = divider(self.core_clock_hz, self.speed_hz);
+
+        self.DIV.write(DIV::CDIV.val(cdiv));
+        // Change SDA a sixteenth of a clock period after the falling edge and sample it a quarter
+        // period after the rising edge.
+        self.DEL
+            .write(DEL::FEDL.val(cmp::max(cdiv / 16, 1)) + DEL::REDL.val(cmp::max(cdiv / 4, 1)));
+
+        let tout = self.speed_hz / 1000 * CLOCK_STRETCH_TIMEOUT_MS;
+        self.CLKT.write(CLKT::TOUT.val(cmp::min(tout, 0xFFFF)));
+    }
+
+    /// Stop any transfer and empty the FIFO.
+    fn reset(&self) {
+        self.C.write(C::I2CEN::SET + C::CLEAR::Fifo);
+        self.S.write(S::CLKT::SET + S::ERR::SET + S::DONE::SET);
+    }
+
+    /// Start a transfer of `len` bytes with `addr`.
+    fn start(&self, addr: u8, len: usize, direction: FieldValue<u32, C::Register>) {
+        self.A.write(A::ADDR.val(addr.into()));
+        self.DLEN.write(DLEN::DLEN.val(len as u32));
+        self.C.write(C::I2CEN::SET + C::ST::SET + direction);
+    }
+
+    /// When a transfer of `len` bytes should be done by, allowing for slow devices.
+    fn deadline(&self, len: usize) -> Duration {
+        // 9 clocks per byte, including the address.
+        let clocks = 9 * (len as u64 + 1);
+        let transfer = Duration::from_micros(clocks * 1_000_000 / u64::from(self.speed_hz));
+
+        arch::timer().uptime()
+            + transfer * 4
+            + Duration::from_millis(CLOCK_STRETCH_TIMEOUT_MS.into())
+    }
+
+    /// Call `poll` until the transfer with `addr` is done, then report how it ended.
+    fn wait_done(
+        &self,
+        addr: u8,
+        deadline: Duration,
+        poll: &mut dyn FnMut(&BSCInner),
+    ) -> Result<()> {
+        loop {
+            poll(self);
+
+            if self.S.is_set(S::CLKT) {
+                self.reset();
+                return Err(Error::ClockStretchTimeout(addr));
+            }
+            if self.S.is_set(S::ERR) {
+                self.reset();
+                return Err(Error::Nack(addr));
+            }
+            if self.S.is_set(S::DONE) {
+                self.S.write(S::DONE::SET);
+                return Ok(());
+            }
+            if arch::timer().uptime() > deadline {
+                self.reset();
+                return Err(Error::Timeout(addr));
+            }
+        }
+    }
+
+    /// Read into `buffer` from the transfer started last.
+    fn receive(&self, addr: u8, buffer: &mut [u8], deadline: Duration) -> Result<()> {
+        let mut received = 0;
+        let mut drain = |inner: &BSCInner| {
+            while received < buffer.len() && inner.S.is_set(S::RXD) {
+                buffer[received] = inner.FIFO.get() as u8;
+                received += 1;
+            }
+        };
+
+        self.wait_done(addr, deadline, &mut drain)?;
+        // The last bytes may arrive together with DONE.
+        drain(self);
+
+        Ok(())
+    }
+
+    fn write(&self, addr: u8, bytes: &[u8]) -> Result<()> {
+        let deadline = self.deadline(bytes.len());
+        let mut pending = bytes.iter();
+
+        self.reset();
+        self.start(addr, bytes.len(), C::READ::Write);
+        self.wait_done(addr, deadline, &mut |inner: &BSCInner| {
+            while inner.S.is_set(S::TXD) {
+                match pending.next() {
+                    Some(&byte) => inner.FIFO.set(byte.into()),
+                    None => break,
+                }
+            }
+        })
+    }
+
+    fn read(&self, addr: u8, buffer: &mut [u8]) -> Result<()> {
+        let deadline = self.deadline(buffer.len());
+
+        self.reset();
+        self.start(addr, buffer.len(), C::READ::Read);
+        self.receive(addr, buffer, deadline)
+    }
+
+    /// The controller has no combined transfer. Instead, the read is queued while the write is
+    /// active, which makes the controller send a repeated start instead of a stop. The write has
+    /// to fit in the FIFO for this.
+    fn write_read(&self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<()> {
+        let deadline = self.deadline(bytes.len() + buffer.len());
+
+        self.reset();
+        bytes.iter().for_each(|&byte| self.FIFO.set(byte.into()));
+        self.start(addr, bytes.len(), C::READ::Write);
+
+        while !self.S.is_set(S::TA) && !self.S.is_set(S::DONE) {
+            if self.S.is_set(S::ERR) {
+                self.reset();
+                return Err(Error::Nack(addr));
+            }
+            if arch::timer().uptime() > deadline {
+                self.reset();
+                return Err(Error::Timeout(addr));
+            }
+        }
+
+        // Too late for a repeated start, the write has ended with a stop. Its DONE must not end
+        // the read.
+        if self.S.is_set(S::DONE) {
+            self.S.write(S::DONE::SET);
+        }
+
+        self.start(addr, buffer.len(), C::READ::Read);
+        self.receive(addr, buffer, deadline)
+    }
+}
+
+/// The even clock divider for the fastest bus speed up to `speed_hz`.
+fn divider(core_clock_hz: u32, speed_hz: u32) -> u32 {
+    let cdiv = (u64::from(core_clock_hz) + u64::from(speed_hz) - 1) / u64::from(speed_hz);
+
+    ((cdiv + 1) & !1) as u32
+}
+
+/// `addr` itself if it is a 7-bit address.
+fn check_addr(addr: u8) -> Result<u8> {
+    if addr < 0x80 {
+        Ok(addr)
+    } else {
+        Err(Error::InvalidAddress(addr))
+    }
+}
+
+/// `len` itself if the controller can transfer that many bytes at once.
+fn check_len(len: usize, max: usize) -> Result<usize> {
+    if len <= max {
+        Ok(len)
+    } else {
+        Err(Error::TooLong(len))
+    }
+}
+
+pub struct BSC {
+    phys_base_addr: usize,
+    inner: Mutex<BSCInner>,
+}
+
+impl BSC {
+    /// Create an instance for the registers at the physical address `phys_base_addr`, clocked by
+    /// a core clock of `core_clock_hz`.
+    pub const unsafe fn new(phys_base_addr: usize, core_clock_hz: u32) -> BSC {
+        BSC {
+            phys_base_addr,
+            inner: Mutex::new(BSCInner::new(phys_base_addr, core_clock_hz)),
+        }
+    }
+}
+
+/// Empty transfers are not supported by the controller and return without touching the bus.
+impl interface::i2c::Master for BSC {
+    fn set_speed(&self, speed_hz: u32) -> Result<()> {
+        let mut inner = self.inner.lock();
+        if speed_hz == 0 || divider(inner.core_clock_hz, speed_hz) > 0xFFFE {
+            return Err(Error::InvalidSpeed(speed_hz));
+        }
+
+        inner.speed_hz = speed_hz;
+        if inner.mmio.is_some() {
+            inner.apply_speed();
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, addr: u8, bytes: &[u8]) -> Result<()> {
+        let addr = check_addr(addr)?;
+        check_len(bytes.len(), MAX_TRANSFER_LEN)?;
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.lock().write(addr, bytes)
+    }
+
+    fn read(&self, addr: u8, buffer: &mut [u8]) -> Result<()> {
+        let addr = check_addr(addr)?;
+        check_len(buffer.len(), MAX_TRANSFER_LEN)?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.lock().read(addr, buffer)
+    }
+
+    fn write_read(&self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<()> {
+        if bytes.is_empty() {
+            return self.read(addr, buffer);
+        }
+        if buffer.is_empty() {
+            return self.write(addr, bytes);
+        }
+
+        let addr = check_addr(addr)?;
+        check_len(bytes.len(), FIFO_SIZE)?;
+        check_len(buffer.len(), MAX_TRANSFER_LEN)?;
+
+        self.inner.lock().write_read(addr, bytes, buffer)
+    }
+}
+
+impl interface::driver::DeviceDriver for BSC {
+    fn compatible(&self) -> &str {
+        "brcm,bcm2835-i2c"
+    }
+
+    /// All BSCs share the `compatible`, so the compiled-in address picks the node.
+    fn fixed_phys_base_addr(&self) -> Option<usize> {
+        Some(self.phys_base_addr)
+    }
+
+    /// The GPIO routes the bus to the pins.
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[gpio::COMPATIBLE]
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        inner.apply_speed();
+        inner.reset();
+
+        Ok(())
+    }
+}
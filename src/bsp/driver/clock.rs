@@ -1,21 +1,70 @@
-use crate::{arch, arch::Mutex, interface::time::Timer};
-use core::ops;
+use crate::{
+    arch,
+    arch::Mutex,
+    interface,
+    interface::time::Timer,
+    memory::{
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
 use core::time::Duration;
+use core::{mem, ops};
 use register::mmio::ReadWrite;
-use register::register_structs;
+use register::{register_bitfields, register_structs};
+
+register_bitfields! {
+    u32,
+
+    /// Clock Control
+    CM_CTL [
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5A
+        ],
+        BUSY OFFSET(7) NUMBITS(1) [],
+        ENAB OFFSET(4) NUMBITS(1) [],
+        SRC OFFSET(0) NUMBITS(4) [
+            Oscillator = 1
+        ]
+    ],
+
+    /// Clock Divisor
+    CM_DIV [
+        PASSWD OFFSET(24) NUMBITS(8) [
+            Password = 0x5A
+        ],
+        DIVI OFFSET(12) NUMBITS(12) []
+    ]
+}
 
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x0000 => _reserved1),
-        (0x1098 => CM_PCM_CTRL: ReadWrite<u32>),
-        (0x109C => CM_PCM_DIV: ReadWrite<u32>),
-        (0x1100 => @END),
+        (0x00 => _reserved1),
+        (0xA0 => CM_PWMCTL: ReadWrite<u32, CM_CTL::Register>),
+        (0xA4 => CM_PWMDIV: ReadWrite<u32, CM_DIV::Register>),
+        (0xA8 => @END),
     }
 }
 
+/// The crystal oscillator, which the PWM clock is divided from.
+#[cfg(feature = "bsp_rpi3")]
+const OSCILLATOR_HZ: u32 = 19_200_000;
+#[cfg(feature = "bsp_rpi4")]
+const OSCILLATOR_HZ: u32 = 54_000_000;
+
+/// Largest integer divisor.
+pub const MAX_DIVISOR: u32 = 0xFFF;
+
+/// The device tree `compatible` of the clock manager.
+#[cfg(feature = "bsp_rpi3")]
+pub const COMPATIBLE: &str = "brcm,bcm2835-cprman";
+#[cfg(feature = "bsp_rpi4")]
+pub const COMPATIBLE: &str = "brcm,bcm2711-cprman";
+
 struct ClockInner {
-    base_addr: usize,
+    phys_base_addr: usize,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
 }
 
 impl ops::Deref for ClockInner {
@@ -27,12 +76,28 @@ impl ops::Deref for ClockInner {
 }
 
 impl ClockInner {
-    const fn new(base_addr: usize) -> ClockInner {
-        ClockInner { base_addr }
+    const fn new(phys_base_addr: usize) -> ClockInner {
+        ClockInner {
+            phys_base_addr,
+            mmio: None,
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
     }
 
     fn ptr(&self) -> *const RegisterBlock {
-        self.base_addr as *const _
+        self.mmio
+            .as_ref()
+            .expect("Clock manager registers are not mapped")
+            .ptr()
     }
 }
 
@@ -41,24 +106,52 @@ pub struct Clock {
 }
 
 impl Clock {
-    pub const unsafe fn new(base_addr: usize) -> Clock {
+    /// Create an instance for the registers at the physical address `phys_base_addr`.
+    pub const unsafe fn new(phys_base_addr: usize) -> Clock {
         Clock {
-            inner: Mutex::new(ClockInner::new(base_addr)),
+            inner: Mutex::new(ClockInner::new(phys_base_addr)),
         }
     }
 
-    pub fn init(&self, divisor: u32) {
-        let inner = &self.inner.lock();
-        let bcm_password = 0x5A00_0000;
+    /// The frequency the PWM clock is divided from.
+    pub const fn source_hz(&self) -> u32 {
+        OSCILLATOR_HZ
+    }
 
-        inner.CM_PCM_CTRL.set(bcm_password | 0x01);
-        arch::timer().spin_for(Duration::from_secs_f32(0.11));
+    /// Start the PWM clock at `source_hz() / divisor`. `divisor` must be 1 to `MAX_DIVISOR`.
+    pub fn start(&self, divisor: u32) {
+        let inner = &self.inner.lock();
 
-        while (inner.CM_PCM_CTRL.get() & 0x80) != 0 {
+        // The divisor must not change while the clock runs.
+        inner
+            .CM_PWMCTL
+            .write(CM_CTL::PASSWD::Password + CM_CTL::SRC::Oscillator);
+        while inner.CM_PWMCTL.is_set(CM_CTL::BUSY) {
             arch::timer().spin_for(Duration::from_secs_f32(0.001));
         }
 
-        inner.CM_PCM_DIV.set(bcm_password | (divisor << 12));
-        inner.CM_PCM_CTRL.set(bcm_password | 0x11);
+        inner
+            .CM_PWMDIV
+            .write(CM_DIV::PASSWD::Password + CM_DIV::DIVI.val(divisor));
+        inner
+            .CM_PWMCTL
+            .write(CM_CTL::PASSWD::Password + CM_CTL::SRC::Oscillator + CM_CTL::ENAB::SET);
+    }
+}
+
+impl interface::driver::DeviceDriver for Clock {
+    fn compatible(&self) -> &str {
+        COMPATIBLE
+    }
+
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        self.inner.get_mut().phys_base_addr = phys_base_addr;
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        Ok(())
     }
 }
@@ -0,0 +1,452 @@
+//! Driver for the BCM2837's legacy DMA controller.
+//!
+//! A transfer is a chain of control blocks in DMA-coherent memory, each moving one run of bytes.
+//! The engine walks the chain on its own; the CPU only starts it and polls for the end. Memory
+//! ends are walked upwards, peripheral ends stay on the data register and wait for its DREQ.
+//!
+//! The firmware uses some of the channels itself, so only those in the channel mask are handed
+//! out. Channel 15 has its registers elsewhere and is never used. The BCM2711's channels 11 to 14
+//! are DMA4 channels with a different layout, which the BSP leaves out of the mask.
+
+use crate::{
+    arch,
+    arch::Mutex,
+    interface,
+    interface::{
+        dma::{Endpoint, Error, Result, Segment},
+        time::Timer,
+    },
+    memory::{
+        dma::{self, DMABuffer},
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use core::{mem, ops, ptr, time::Duration};
+use register::{mmio::ReadWrite, register_bitfields, register_structs, FieldValue};
+
+register_bitfields! {
+    u32,
+
+    /// Control and Status
+    CS [
+        RESET OFFSET(31) NUMBITS(1) [],
+        WAIT_FOR_OUTSTANDING_WRITES OFFSET(28) NUMBITS(1) [],
+        ERROR OFFSET(8) NUMBITS(1) [],
+        END OFFSET(1) NUMBITS(1) [],
+        ACTIVE OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Transfer Information, of a control block
+    TI [
+        PERMAP OFFSET(16) NUMBITS(5) [],
+        SRC_DREQ OFFSET(10) NUMBITS(1) [],
+        SRC_INC OFFSET(8) NUMBITS(1) [],
+        DEST_DREQ OFFSET(6) NUMBITS(1) [],
+        DEST_INC OFFSET(4) NUMBITS(1) [],
+        WAIT_RESP OFFSET(3) NUMBITS(1) []
+    ],
+
+    /// Debug, with the error flags. Writing 1 clears a flag.
+    DEBUG [
+        /// Set on the lite channels, which have a smaller TXFR_LEN and no 2D mode.
+        LITE OFFSET(28) NUMBITS(1) [],
+        READ_ERROR OFFSET(2) NUMBITS(1) [],
+        FIFO_ERROR OFFSET(1) NUMBITS(1) [],
+        READ_LAST_NOT_SET_ERROR OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    ChannelBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CONBLK_AD: ReadWrite<u32>),
+        (0x08 => _reserved1),
+        (0x20 => DEBUG: ReadWrite<u32, DEBUG::Register>),
+        (0x24 => _reserved2),
+        (0x100 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => CHANNELS: [ChannelBlock; NUM_CHANNELS]),
+        (0xF00 => _reserved1),
+        (0xFF0 => ENABLE: ReadWrite<u32>),
+        (0xFF4 => @END),
+    }
+}
+
+/// Channels 0 to 14, which share the legacy register layout.
+const NUM_CHANNELS: usize = 15;
+
+/// The most a control block moves. TXFR_LEN has 30 bits, or 16 on the lite channels, and a
+/// multiple of the word size keeps the following blocks aligned.
+const MAX_BLOCK_LEN: usize = 0x3FFF_FFFC;
+const LITE_MAX_BLOCK_LEN: usize = 0xFFFC;
+
+/// A control block as the engine reads it from memory.
+#[repr(C, align(32))]
+#[derive(Copy, Clone)]
+struct ControlBlock {
+    ti: u32,
+    source_ad: u32,
+    dest_ad: u32,
+    txfr_len: u32,
+    stride: u32,
+    nextconbk: u32,
+    _reserved: [u32; 2],
+}
+
+struct DMAInner {
+    phys_base_addr: usize,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
+    channel_mask: u16,
+    /// Channels handed out, one bit each.
+    allocated: u16,
+}
+
+impl ops::Deref for DMAInner {
+    type Target = RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr() }
+    }
+}
+
+impl DMAInner {
+    const fn new(phys_base_addr: usize, channel_mask: u16) -> DMAInner {
+        DMAInner {
+            phys_base_addr,
+            mmio: None,
+            channel_mask,
+            allocated: 0,
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> core::result::Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
+    }
+
+    fn ptr(&self) -> *const RegisterBlock {
+        self.mmio
+            .as_ref()
+            .expect("DMA registers are not mapped")
+            .ptr()
+    }
+
+    /// Usable channels, without the ones of other layouts.
+    fn usable_channels(&self) -> u16 {
+        self.channel_mask & ((1 << NUM_CHANNELS) - 1)
+    }
+
+    fn allocate(&mut self) -> Result<usize> {
+        if self.mmio.is_none() {
+            return Err(Error::Unavailable);
+        }
+
+        let free = self.usable_channels() & !self.allocated;
+        if free == 0 {
+            return Err(Error::NoFreeChannel);
+        }
+
+        let index = free.trailing_zeros() as usize;
+        self.allocated |= 1 << index;
+        self.CHANNELS[index].CS.write(CS::RESET::SET);
+
+        Ok(index)
+    }
+
+    fn release(&mut self, index: usize) {
+        self.CHANNELS[index].CS.write(CS::RESET::SET);
+        self.allocated &= !(1 << index);
+    }
+
+    /// The most a control block of channel `index` moves.
+    fn max_block_len(&self, index: usize) -> usize {
+        if self.CHANNELS[index].DEBUG.is_set(DEBUG::LITE) {
+            LITE_MAX_BLOCK_LEN
+        } else {
+            MAX_BLOCK_LEN
+        }
+    }
+
+    /// Start channel `index` on the chain in `blocks`.
+    fn start(&self, index: usize, blocks: &DMABuffer) -> Result<()> {
+        let channel = &self.CHANNELS[index];
+        if channel.CS.is_set(CS::ACTIVE) {
+            return Err(Error::Busy);
+        }
+
+        channel.DEBUG.write(
+            DEBUG::READ_ERROR::SET + DEBUG::FIFO_ERROR::SET + DEBUG::READ_LAST_NOT_SET_ERROR::SET,
+        );
+        channel.CS.write(CS::END::SET);
+        channel.CONBLK_AD.set(blocks.bus_addr() as u32);
+        channel
+            .CS
+            .write(CS::ACTIVE::SET + CS::WAIT_FOR_OUTSTANDING_WRITES::SET);
+
+        Ok(())
+    }
+
+    /// Whether channel `index` is through its chain. On an error, the channel is reset.
+    fn poll(&self, index: usize) -> Result<bool> {
+        let channel = &self.CHANNELS[index];
+        let cs = channel.CS.extract();
+
+        if cs.is_set(CS::ERROR) {
+            let debug = channel.DEBUG.extract();
+            channel.CS.write(CS::RESET::SET);
+
+            return Err(if debug.is_set(DEBUG::READ_ERROR) {
+                Error::ReadError
+            } else if debug.is_set(DEBUG::FIFO_ERROR) {
+                Error::FifoError
+            } else {
+                Error::ReadLastNotSet
+            });
+        }
+
+        Ok(!cs.is_set(CS::ACTIVE) && channel.CONBLK_AD.get() == 0)
+    }
+}
+
+/// The transfer information of a block from `src` to `dest`.
+fn transfer_info(src: Endpoint, dest: Endpoint) -> Result<FieldValue<u32, TI::Register>> {
+    let mut ti = TI::WAIT_RESP::SET;
+
+    match (src, dest) {
+        (Endpoint::Peripheral(..), Endpoint::Peripheral(..)) => return Err(Error::InvalidSegment),
+        (Endpoint::Peripheral(_, dreq), _) => ti += TI::SRC_DREQ::SET + TI::PERMAP.val(dreq),
+        (Endpoint::Memory(_), _) => ti += TI::SRC_INC::SET,
+    }
+    match dest {
+        Endpoint::Peripheral(_, dreq) => ti += TI::DEST_DREQ::SET + TI::PERMAP.val(dreq),
+        Endpoint::Memory(_) => ti += TI::DEST_INC::SET,
+    }
+
+    Ok(ti)
+}
+
+/// The bus address of the byte at `offset` from `endpoint`.
+fn endpoint_addr(endpoint: Endpoint, offset: usize) -> u32 {
+    match endpoint {
+        Endpoint::Memory(bus_addr) => (bus_addr + offset) as u32,
+        Endpoint::Peripheral(bus_addr, _) => bus_addr as u32,
+    }
+}
+
+/// Chain control blocks for `segments`, splitting segments longer than `max_block_len`, in a new
+/// coherent buffer.
+fn build_chain(segments: &[Segment], max_block_len: usize) -> Result<DMABuffer> {
+    if segments.is_empty() || segments.iter().any(|segment| segment.len == 0) {
+        return Err(Error::InvalidSegment);
+    }
+
+    let count: usize = segments
+        .iter()
+        .map(|segment| (segment.len + max_block_len - 1) / max_block_len)
+        .sum();
+    let block_size = mem::size_of::<ControlBlock>();
+    let blocks =
+        dma::alloc(count * block_size, mem::align_of::<ControlBlock>()).ok_or(Error::NoMemory)?;
+    let ptr = blocks.as_mut_ptr() as *mut ControlBlock;
+
+    let mut index = 0;
+    for segment in segments {
+        let ti = transfer_info(segment.src, segment.dest)?;
+
+        for offset in (0..segment.len).step_by(max_block_len) {
+            let next = match index + 1 {
+                next if next == count => 0,
+                next => (blocks.bus_addr() + next * block_size) as u32,
+            };
+            let block = ControlBlock {
+                ti: ti.value,
+                source_ad: endpoint_addr(segment.src, offset),
+                dest_ad: endpoint_addr(segment.dest, offset),
+                txfr_len: (segment.len - offset).min(max_block_len) as u32,
+                stride: 0,
+                nextconbk: next,
+                _reserved: [0; 2],
+            };
+
+            unsafe { ptr::write_volatile(ptr.add(index), block) };
+            index += 1;
+        }
+    }
+    // The chain must be in memory before the engine reads it.
+    arch::cache::data_sync_barrier();
+
+    Ok(blocks)
+}
+
+pub struct DMA {
+    inner: Mutex<DMAInner>,
+}
+
+impl DMA {
+    /// Create an instance for the registers at the physical address `phys_base_addr`, handing
+    /// out the channels set in `channel_mask`.
+    pub const unsafe fn new(phys_base_addr: usize, channel_mask: u16) -> DMA {
+        DMA {
+            inner: Mutex::new(DMAInner::new(phys_base_addr, channel_mask)),
+        }
+    }
+
+    /// Hand out the channels set in `channel_mask` instead, e.g. those the firmware's device tree
+    /// leaves to the ARM. Must be called before `init()`.
+    pub fn set_channel_mask(&mut self, channel_mask: u16) {
+        self.inner.get_mut().channel_mask = channel_mask;
+    }
+
+    /// Take a free channel, until the `Channel` is dropped.
+    #[allow(dead_code)]
+    pub fn channel(&'static self) -> Result<Channel> {
+        let index = self.inner.lock().allocate()?;
+
+        Ok(Channel {
+            dma: self,
+            index,
+            blocks: None,
+        })
+    }
+
+    /// Copy `src` to the start of `dest` and wait for it, for at most `timeout`.
+    pub fn copy(&self, dest: &DMABuffer, src: &DMABuffer, timeout: Duration) -> Result<()> {
+        if src.size() > dest.size() {
+            return Err(Error::InvalidSegment);
+        }
+
+        let segment = Segment {
+            src: Endpoint::Memory(src.bus_addr()),
+            dest: Endpoint::Memory(dest.bus_addr()),
+            len: src.size(),
+        };
+        unsafe { interface::dma::Engine::transfer(self, &[segment], timeout) }
+    }
+
+    /// Poll channel `index` until it is through its chain, resetting it after `timeout`. The
+    /// lock is released between polls.
+    fn wait(&self, index: usize, timeout: Duration) -> Result<()> {
+        let deadline = arch::timer().uptime() + timeout;
+
+        while !self.inner.lock().poll(index)? {
+            if arch::timer().uptime() > deadline {
+                self.inner.lock().CHANNELS[index].CS.write(CS::RESET::SET);
+                return Err(Error::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A channel of the controller, owned until dropped. Dropping it aborts its transfer.
+pub struct Channel {
+    dma: &'static DMA,
+    index: usize,
+    /// The chain of the last transfer, kept until the next one.
+    blocks: Option<DMABuffer>,
+}
+
+#[allow(dead_code)]
+impl Channel {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Start running `segments` in order, without waiting for them.
+    ///
+    /// # Safety
+    ///
+    /// - The memory endpoints must be DMA-coherent and valid until the transfer is done or the
+    ///   channel is dropped.
+    pub unsafe fn start(&mut self, segments: &[Segment]) -> Result<()> {
+        let max_block_len = self.dma.inner.lock().max_block_len(self.index);
+        let blocks = build_chain(segments, max_block_len)?;
+        self.dma.inner.lock().start(self.index, &blocks)?;
+        self.blocks = Some(blocks);
+
+        Ok(())
+    }
+
+    /// Whether the transfer is done. Fails with the error that stopped it.
+    pub fn is_done(&self) -> Result<bool> {
+        self.dma.inner.lock().poll(self.index)
+    }
+
+    /// Wait for the transfer to finish, for at most `timeout`.
+    pub fn wait(&self, timeout: Duration) -> Result<()> {
+        self.dma.wait(self.index, timeout)
+    }
+
+    /// Stop the transfer, leaving the data moved so far.
+    pub fn abort(&self) {
+        self.dma.inner.lock().CHANNELS[self.index]
+            .CS
+            .write(CS::RESET::SET);
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.dma.inner.lock().release(self.index);
+    }
+}
+
+impl interface::dma::Engine for DMA {
+    unsafe fn transfer(&self, segments: &[Segment], timeout: Duration) -> Result<()> {
+        let (index, max_block_len) = {
+            let mut inner = self.inner.lock();
+            let index = inner.allocate()?;
+
+            (index, inner.max_block_len(index))
+        };
+
+        // The chain is freed only after the channel was reset on release.
+        let chain = build_chain(segments, max_block_len);
+        let result = match &chain {
+            Ok(blocks) => {
+                let started = self.inner.lock().start(index, blocks);
+                started.and_then(|_| self.wait(index, timeout))
+            }
+            Err(err) => Err(*err),
+        };
+        self.inner.lock().release(index);
+
+        result
+    }
+}
+
+impl interface::driver::DeviceDriver for DMA {
+    fn compatible(&self) -> &str {
+        "brcm,bcm2835-dma"
+    }
+
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        self.inner.get_mut().phys_base_addr = phys_base_addr;
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        let usable = inner.usable_channels();
+        for index in (0..NUM_CHANNELS).filter(|index| usable & (1 << index) != 0) {
+            inner.CHANNELS[index].CS.write(CS::RESET::SET);
+        }
+        inner.ENABLE.set(inner.ENABLE.get() | u32::from(usable));
+
+        Ok(())
+    }
+}
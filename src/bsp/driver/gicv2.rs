@@ -0,0 +1,256 @@
+//! Driver for a GICv2 interrupt controller, like the GIC-400 of the BCM2711 or the one QEMU's virt
+//! machine emulates.
+//!
+//! The distributor (GICD) routes the shared peripheral interrupts (SPIs, 32 and above) to the
+//! cores. Each core acknowledges and completes interrupts through its banked CPU interface (GICC).
+
+use crate::{
+    arch::Mutex,
+    interface,
+    memory::{
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use core::mem;
+use register::mmio::{ReadOnly, ReadWrite, WriteOnly};
+use register::{register_bitfields, register_structs};
+
+register_bitfields! {
+    u32,
+
+    /// Distributor Control Register
+    GICD_CTLR [
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Controller Type Register
+    GICD_TYPER [
+        /// The distributor supports 32 * (ITLinesNumber + 1) interrupts.
+        ITLinesNumber OFFSET(0) NUMBITS(5) []
+    ],
+
+    /// CPU Interface Control Register
+    GICC_CTLR [
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Priority Mask Register
+    GICC_PMR [
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+
+    /// Interrupt Acknowledge Register
+    GICC_IAR [
+        InterruptID OFFSET(0) NUMBITS(10) []
+    ],
+
+    /// End of Interrupt Register
+    GICC_EOIR [
+        EOIINTID OFFSET(0) NUMBITS(10) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    GICDRegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, GICD_CTLR::Register>),
+        (0x004 => TYPER: ReadOnly<u32, GICD_TYPER::Register>),
+        (0x008 => _reserved1),
+        (0x100 => ISENABLER: [ReadWrite<u32>; 32]),
+        (0x180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x200 => _reserved2),
+        (0x400 => IPRIORITYR: [ReadWrite<u32>; 255]),
+        (0x7FC => _reserved3),
+        (0x800 => ITARGETSR: [ReadWrite<u32>; 255]),
+        (0xBFC => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    GICCRegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, GICC_CTLR::Register>),
+        (0x004 => PMR: ReadWrite<u32, GICC_PMR::Register>),
+        (0x008 => _reserved1),
+        (0x00C => IAR: ReadOnly<u32, GICC_IAR::Register>),
+        (0x010 => EOIR: WriteOnly<u32, GICC_EOIR::Register>),
+        (0x014 => @END),
+    }
+}
+
+/// Interrupt ID the CPU interface returns when nothing is pending.
+const SPURIOUS_INTERRUPT_ID: u32 = 1023;
+
+/// First shared peripheral interrupt. Lower IDs are banked per core.
+const FIRST_SPI: usize = 32;
+
+struct GICv2Inner {
+    gicd_phys_base_addr: usize,
+    gicc_phys_base_addr: usize,
+    gicd: Option<MMIOMapping<GICDRegisterBlock>>,
+    gicc: Option<MMIOMapping<GICCRegisterBlock>>,
+}
+
+impl GICv2Inner {
+    const fn new(gicd_phys_base_addr: usize, gicc_phys_base_addr: usize) -> GICv2Inner {
+        GICv2Inner {
+            gicd_phys_base_addr,
+            gicc_phys_base_addr,
+            gicd: None,
+            gicc: None,
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> Result<(), MapError> {
+        if self.gicd.is_none() {
+            let size = mem::size_of::<GICDRegisterBlock>();
+            self.gicd = Some(mmio::ioremap(self.gicd_phys_base_addr, size)?);
+        }
+        if self.gicc.is_none() {
+            let size = mem::size_of::<GICCRegisterBlock>();
+            self.gicc = Some(mmio::ioremap(self.gicc_phys_base_addr, size)?);
+        }
+
+        Ok(())
+    }
+
+    fn gicd(&self) -> &GICDRegisterBlock {
+        unsafe {
+            &*self
+                .gicd
+                .as_ref()
+                .expect("GICD registers are not mapped")
+                .ptr()
+        }
+    }
+
+    fn gicc(&self) -> &GICCRegisterBlock {
+        unsafe {
+            &*self
+                .gicc
+                .as_ref()
+                .expect("GICC registers are not mapped")
+                .ptr()
+        }
+    }
+
+    /// Number of interrupt IDs the distributor implements.
+    fn num_irqs(&self) -> usize {
+        let lines = self.gicd().TYPER.read(GICD_TYPER::ITLinesNumber) as usize;
+
+        ((lines + 1) * 32).min(SPURIOUS_INTERRUPT_ID as usize)
+    }
+
+    /// Route all SPIs to core 0 with the same priority and leave them disabled.
+    fn init_distributor(&self) {
+        let gicd = self.gicd();
+        let num_irqs = self.num_irqs();
+
+        gicd.CTLR.write(GICD_CTLR::Enable::CLEAR);
+
+        for reg in &gicd.ICENABLER[FIRST_SPI / 32..num_irqs / 32] {
+            reg.set(0xFFFF_FFFF);
+        }
+        // One byte per interrupt.
+        for reg in &gicd.IPRIORITYR[FIRST_SPI / 4..num_irqs / 4] {
+            reg.set(0xA0A0_A0A0);
+        }
+        for reg in &gicd.ITARGETSR[FIRST_SPI / 4..num_irqs / 4] {
+            reg.set(0x0101_0101);
+        }
+
+        gicd.CTLR.write(GICD_CTLR::Enable::SET);
+    }
+
+    /// Let interrupts of any priority through to the calling core.
+    fn init_cpu_interface(&self) {
+        let gicc = self.gicc();
+
+        gicc.PMR.write(GICC_PMR::Priority.val(0xFF));
+        gicc.CTLR.write(GICC_CTLR::Enable::SET);
+    }
+}
+
+pub struct GICv2 {
+    inner: Mutex<GICv2Inner>,
+}
+
+impl GICv2 {
+    /// Create an instance for the distributor and CPU interface registers at the given physical
+    /// addresses.
+    pub const unsafe fn new(gicd_phys_base_addr: usize, gicc_phys_base_addr: usize) -> GICv2 {
+        GICv2 {
+            inner: Mutex::new(GICv2Inner::new(gicd_phys_base_addr, gicc_phys_base_addr)),
+        }
+    }
+
+    /// Use the CPU interface registers at `phys_base_addr` instead of the compiled-in address.
+    /// Must be called before `init()`.
+    pub fn set_cpu_interface_phys_base_addr(&mut self, phys_base_addr: usize) {
+        self.inner.get_mut().gicc_phys_base_addr = phys_base_addr;
+    }
+
+    /// Enable the CPU interface of the calling core. `init()` does so for the boot core, the
+    /// others must call this themselves.
+    #[allow(dead_code)]
+    pub fn init_cpu_interface(&self) {
+        self.inner.lock().init_cpu_interface();
+    }
+}
+
+impl interface::driver::DeviceDriver for GICv2 {
+    fn compatible(&self) -> &str {
+        if cfg!(feature = "bsp_qemu_virt") {
+            "arm,cortex-a15-gic"
+        } else {
+            "arm,gic-400"
+        }
+    }
+
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        self.inner.get_mut().gicd_phys_base_addr = phys_base_addr;
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        inner.init_distributor();
+        inner.init_cpu_interface();
+
+        Ok(())
+    }
+}
+
+impl interface::irq::Manager for GICv2 {
+    fn enable(&self, irq: u32) {
+        let inner = self.inner.lock();
+        let irq = irq as usize;
+
+        inner.gicd().ISENABLER[irq / 32].set(1 << (irq % 32));
+    }
+
+    fn disable(&self, irq: u32) {
+        let inner = self.inner.lock();
+        let irq = irq as usize;
+
+        inner.gicd().ICENABLER[irq / 32].set(1 << (irq % 32));
+    }
+
+    fn acknowledge(&self) -> Option<u32> {
+        let inner = self.inner.lock();
+
+        match inner.gicc().IAR.read(GICC_IAR::InterruptID) {
+            SPURIOUS_INTERRUPT_ID => None,
+            irq => Some(irq),
+        }
+    }
+
+    fn end_of_interrupt(&self, irq: u32) {
+        let inner = self.inner.lock();
+
+        inner.gicc().EOIR.write(GICC_EOIR::EOIINTID.val(irq));
+    }
+}
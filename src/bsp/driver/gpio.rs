@@ -1,120 +1,69 @@
-use crate::interface::pwm::All as PWMAll;
-use crate::{arch, arch::Mutex, bsp, interface, interface::time::Timer};
-use core::{ops, time::Duration};
+use crate::interface::{
+    gpio::{Dir, Error, Function, Pud, Result},
+    pwm::Channel,
+};
+use crate::{
+    arch::Mutex,
+    interface,
+    memory::{
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use core::{convert::Infallible, marker::PhantomData, mem, ops};
+use embedded_hal::digital::v2 as hal;
 use register::mmio::{ReadOnly, ReadWrite, WriteOnly};
-use register::{register_bitfields, register_structs};
-
-register_bitfields! {
-    u32,
-
-    GPFSEL0 [
-        FSEL2 OFFSET(6) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001
-        ],
-
-        FSEL1 OFFSET(3) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001
-        ],
-
-        FSEL0 OFFSET(0) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001
-        ]
-    ],
-
-    GPFSEL1 [
-        FSEL19 OFFSET(27) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc5 = 0b010
-        ],
-
-        FSEL18 OFFSET(24) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc5 = 0b010
-        ],
-
-        FSEL15 OFFSET(15) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100
-        ],
-
-        FSEL14 OFFSET(12) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100
-        ],
-
-        FSEL13 OFFSET(9) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100 // PWM Channel 0
-        ],
-
-        FSEL12 OFFSET(6) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100 // PWM Channel 0
-        ]
-    ],
-
-    GPCLR0 [
-        CLR2 OFFSET(2) NUMBITS(1) [
-            NoEffect = 0,
-            Clear = 1
-        ],
-
-        CLR1 OFFSET(1) NUMBITS(1) [
-            NoEffect = 0,
-            Clear = 1
-        ],
-
-        CLR0 OFFSET(0) NUMBITS(1) [
-            NoEffect = 0,
-            Clear = 1
-        ]
-    ],
-
-    GPLEV0 [
-        LEV3 OFFSET(3) NUMBITS(1) [],
-        LEV2 OFFSET(2) NUMBITS(1) [],
-        LEV1 OFFSET(1) NUMBITS(1) [],
-        LEV0 OFFSET(0) NUMBITS(1) []
-    ]
-}
+use register::register_structs;
 
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => GPFSEL0: ReadWrite<u32, GPFSEL0::Register>),
-        (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => GPFSEL2: ReadWrite<u32>),
-        (0x0C => GPFSEL3: ReadWrite<u32>),
-        (0x10 => GPFSEL4: ReadWrite<u32>),
-        (0x14 => GPFSEL5: ReadWrite<u32>),
+        (0x00 => GPFSEL: [ReadWrite<u32>; 6]),
         (0x18 => _reserved1),
-        (0x1c => GPSET0: WriteOnly<u32>),
-        (0x20 => GPSET1: WriteOnly<u32>),
+        (0x1c => GPSET: [WriteOnly<u32>; 2]),
         (0x24 => _reserved2),
-        (0x28 => GPCLR0: WriteOnly<u32>),
-        (0x2c => GPCLR1: WriteOnly<u32>),
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
         (0x30 => _reserved3),
-        (0x34 => GPLEV0: ReadOnly<u32>),
-        (0x38 => GPLEV1: ReadOnly<u32>),
+        (0x34 => GPLEV: [ReadOnly<u32>; 2]),
         (0x3c => _reserved4),
         (0x94 => GPPUD: ReadWrite<u32>),
-        (0x98 => GPPUDCLK0: ReadWrite<u32>),
-        (0x9C => GPPUDCLK1: ReadWrite<u32>),
-        (0xA0 => @END),
+        (0x98 => GPPUDCLK: [ReadWrite<u32>; 2]),
+        (0xA0 => _reserved5),
+        // BCM2711 only. Replaces GPPUD and GPPUDCLKn.
+        (0xE4 => GPIO_PUP_PDN_CNTRL_REG: [ReadWrite<u32>; 4]),
+        (0xF4 => @END),
     }
 }
 
+/// Number of pins, in two banks of 32 and 22.
+const NUM_PINS: u32 = 54;
+
+/// Pins per function select register, 3 bits each.
+const PINS_PER_GPFSEL: usize = 10;
+
+/// The device tree `compatible` of the GPIO controller.
+#[cfg(feature = "bsp_rpi3")]
+pub const COMPATIBLE: &str = "brcm,bcm2835-gpio";
+#[cfg(feature = "bsp_rpi4")]
+pub const COMPATIBLE: &str = "brcm,bcm2711-gpio";
+
+/// Mode markers of `Pin`.
+pub mod mode {
+    /// A floating input.
+    pub struct Input;
+    /// A push-pull output.
+    pub struct Output;
+    /// Connected to a peripheral through one of the alternate functions.
+    pub struct Alt;
+    /// Connected to a channel of the PWM.
+    pub struct Pwm;
+}
+
 struct GPIOInner {
-    base_addr: usize,
+    phys_base_addr: usize,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
+    /// Pins owned by a `Pin`, bit n standing for pin n.
+    claimed: u64,
 }
 
 impl ops::Deref for GPIOInner {
@@ -126,12 +75,156 @@ impl ops::Deref for GPIOInner {
 }
 
 impl GPIOInner {
-    const fn new(base_addr: usize) -> GPIOInner {
-        GPIOInner { base_addr }
+    const fn new(phys_base_addr: usize) -> GPIOInner {
+        GPIOInner {
+            phys_base_addr,
+            mmio: None,
+            claimed: 0,
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
     }
 
     fn ptr(&self) -> *const RegisterBlock {
-        self.base_addr as *const _
+        self.mmio
+            .as_ref()
+            .expect("GPIO registers are not mapped")
+            .ptr()
+    }
+
+    /// Select the function of `pin`, which must be valid.
+    fn set_function(&self, pin: u32, function: Function) {
+        let fsel = match function {
+            Function::Input => 0b000,
+            Function::Output => 0b001,
+            Function::Alt0 => 0b100,
+            Function::Alt1 => 0b101,
+            Function::Alt2 => 0b110,
+            Function::Alt3 => 0b111,
+            Function::Alt4 => 0b011,
+            Function::Alt5 => 0b010,
+        };
+        let reg = &self.GPFSEL[pin as usize / PINS_PER_GPFSEL];
+        let shift = (pin as usize % PINS_PER_GPFSEL) * 3;
+
+        reg.set((reg.get() & !(0b111 << shift)) | (fsel << shift));
+    }
+
+    /// The function `pin`, which must be valid, is set to.
+    fn function(&self, pin: u32) -> Function {
+        let reg = &self.GPFSEL[pin as usize / PINS_PER_GPFSEL];
+        let shift = (pin as usize % PINS_PER_GPFSEL) * 3;
+
+        match (reg.get() >> shift) & 0b111 {
+            0b000 => Function::Input,
+            0b001 => Function::Output,
+            0b100 => Function::Alt0,
+            0b101 => Function::Alt1,
+            0b110 => Function::Alt2,
+            0b111 => Function::Alt3,
+            0b011 => Function::Alt4,
+            _ => Function::Alt5,
+        }
+    }
+
+    /// Whether `pin`, which must be valid, reads high.
+    fn level(&self, pin: u32) -> bool {
+        let (bank, bit) = bank_bit(pin);
+
+        self.GPLEV[bank].get() & bit != 0
+    }
+
+    /// `pin` itself if the controller has it and no `Pin` owns it.
+    fn check_unclaimed(&self, pin: u32) -> Result<u32> {
+        let pin = check_pin(pin)?;
+
+        if self.claimed & (1 << pin) != 0 {
+            Err(Error::PinInUse(pin))
+        } else {
+            Ok(pin)
+        }
+    }
+
+    /// Drive `pin`, which must be valid, high or low.
+    fn set_level(&self, pin: u32, high: bool) {
+        let (bank, bit) = bank_bit(pin);
+
+        if high {
+            self.GPSET[bank].set(bit);
+        } else {
+            self.GPCLR[bank].set(bit);
+        }
+    }
+
+    /// Set the pull-up/down of the pins in the mask `pins`, bit n standing for pin n, with the
+    /// BCM2837's clocked sequence.
+    #[cfg(feature = "bsp_rpi3")]
+    fn set_pull(&self, pins: u64, pud: &Pud) {
+        let pull = match pud {
+            Pud::PudOff => 0,
+            Pud::PudUp => 1,
+            Pud::PudDown => 2,
+        };
+
+        self.GPPUD.set(pull);
+        crate::arch::spin_for_cycles(150);
+
+        self.GPPUDCLK[0].set(pins as u32);
+        self.GPPUDCLK[1].set((pins >> 32) as u32);
+        crate::arch::spin_for_cycles(150);
+
+        self.GPPUD.set(0);
+        self.GPPUDCLK[0].set(0);
+        self.GPPUDCLK[1].set(0);
+    }
+
+    /// Set the pull-up/down of the pins in the mask `pins`, bit n standing for pin n, through the
+    /// BCM2711's per-pin control registers, 2 bits per pin.
+    #[cfg(feature = "bsp_rpi4")]
+    fn set_pull(&self, pins: u64, pud: &Pud) {
+        let pull = match pud {
+            Pud::PudOff => 0b00,
+            Pud::PudUp => 0b01,
+            Pud::PudDown => 0b10,
+        };
+
+        for pin in (0..NUM_PINS as usize).filter(|pin| pins & (1 << pin) != 0) {
+            let reg = &self.GPIO_PUP_PDN_CNTRL_REG[pin / 16];
+            let shift = (pin % 16) * 2;
+
+            reg.set((reg.get() & !(0b11 << shift)) | (pull << shift));
+        }
+    }
+}
+
+/// `pin` itself if the controller has it.
+fn check_pin(pin: u32) -> Result<u32> {
+    if pin < NUM_PINS {
+        Ok(pin)
+    } else {
+        Err(Error::InvalidPin(pin))
+    }
+}
+
+/// The bank of `pin` and its bit in the bank's registers.
+fn bank_bit(pin: u32) -> (usize, u32) {
+    ((pin / 32) as usize, 1 << (pin % 32))
+}
+
+/// The alternate function connecting `pin` to the PWM, if it has one.
+fn pwm_function(pin: u32) -> Option<Function> {
+    match pin {
+        12 | 13 | 40 | 41 | 45 => Some(Function::Alt0),
+        18 | 19 => Some(Function::Alt5),
+        _ => None,
     }
 }
 
@@ -140,129 +233,313 @@ pub struct GPIO {
 }
 
 impl GPIO {
-    pub const unsafe fn new(base_addr: usize) -> GPIO {
+    /// Create an instance for the registers at the physical address `phys_base_addr`.
+    pub const unsafe fn new(phys_base_addr: usize) -> GPIO {
         GPIO {
-            inner: Mutex::new(GPIOInner::new(base_addr)),
+            inner: Mutex::new(GPIOInner::new(phys_base_addr)),
         }
     }
 
-    pub fn map_pl011_uart(&self) {
-        let inner = &self.inner.lock();
-        inner
-            .GPFSEL1
-            .modify(GPFSEL1::FSEL14::AltFunc0 + GPFSEL1::FSEL15::AltFunc0);
+    /// Take ownership of `pin` and make it an input. Fails with `Error::PinInUse` until the
+    /// previous owner releases it.
+    pub fn take(&'static self, pin: u32) -> Result<Pin<mode::Input>> {
+        let mut inner = self.inner.lock();
+        let pin = inner.check_unclaimed(pin)?;
 
-        inner.GPPUD.set(0);
-        arch::spin_for_cycles(150);
+        inner.claimed |= 1 << pin;
+        inner.set_function(pin, Function::Input);
 
-        let modified = (inner.GPPUDCLK0.get() as u32) | (1 << 14) | (1 << 15);
-        inner.GPPUDCLK0.set(modified);
+        Ok(Pin {
+            number: pin,
+            gpio: self,
+            mode: PhantomData,
+        })
+    }
+
+    /// Claim GPIO 14 and 15 and route the PL011's TX and RX to them.
+    #[allow(dead_code)]
+    pub fn map_pl011_uart(&'static self) -> Result<[Pin<mode::Alt>; 2]> {
+        self.map_uart(Function::Alt0)
+    }
+
+    /// Claim GPIO 14 and 15 and route the mini UART's TX and RX to them, in place of the PL011.
+    #[allow(dead_code)]
+    pub fn map_mini_uart(&'static self) -> Result<[Pin<mode::Alt>; 2]> {
+        self.map_uart(Function::Alt5)
+    }
 
-        arch::spin_for_cycles(150);
+    /// Claim GPIO 2 and 3 and route BSC1's SDA and SCL to them. The board pulls both lines up.
+    #[allow(dead_code)]
+    pub fn map_i2c1(&'static self) -> Result<[Pin<mode::Alt>; 2]> {
+        let sda = self.take(2)?.into_alt(Function::Alt0)?;
+        let scl = self.take(3)?.into_alt(Function::Alt0)?;
 
-        inner.GPPUDCLK0.set(0);
+        Ok([sda, scl])
+    }
+
+    /// Claim GPIO 7 to 11 and route SPI0's CE1, CE0, MISO, MOSI and SCLK to them, in this
+    /// order.
+    #[allow(dead_code)]
+    pub fn map_spi0(&'static self) -> Result<[Pin<mode::Alt>; 5]> {
+        let ce1 = self.take(7)?.into_alt(Function::Alt0)?;
+        let ce0 = self.take(8)?.into_alt(Function::Alt0)?;
+        let miso = self.take(9)?.into_alt(Function::Alt0)?;
+        let mosi = self.take(10)?.into_alt(Function::Alt0)?;
+        let sclk = self.take(11)?.into_alt(Function::Alt0)?;
+
+        Ok([ce1, ce0, miso, mosi, sclk])
+    }
+
+    fn map_uart(&'static self, function: Function) -> Result<[Pin<mode::Alt>; 2]> {
+        let tx = self.take(14)?.into_alt(function)?;
+        let rx = self.take(15)?.into_alt(function)?;
+
+        tx.set_pull(Pud::PudOff);
+        rx.set_pull(Pud::PudOff);
+
+        Ok([tx, rx])
     }
 }
 
-use interface::gpio::{Dir, Pud};
+/// An owned GPIO pin in `Mode`. Only one `Pin` exists per pin number at a time; dropping it
+/// releases the pin.
+pub struct Pin<Mode> {
+    number: u32,
+    gpio: &'static GPIO,
+    mode: PhantomData<Mode>,
+}
 
-impl interface::gpio::Set for GPIO {
-    fn pullupdn(&self, pin: u32, pud: Pud) {
-        let pull = match pud {
-            Pud::PudOff => 0,
-            Pud::PudUp => 1,
-            Pud::PudDown => 2,
+#[allow(dead_code)]
+impl<Mode> Pin<Mode> {
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The function the pin is set to.
+    pub fn function(&self) -> Function {
+        self.gpio.inner.lock().function(self.number)
+    }
+
+    pub fn set_pull(&self, pud: Pud) {
+        self.gpio.inner.lock().set_pull(1 << self.number, &pud);
+    }
+
+    pub fn into_input(self) -> Pin<mode::Input> {
+        self.into_mode(Function::Input)
+    }
+
+    /// Make the pin an output, driven low.
+    pub fn into_output(self) -> Pin<mode::Output> {
+        self.gpio.inner.lock().set_level(self.number, false);
+        self.into_mode(Function::Output)
+    }
+
+    /// Connect the pin to a peripheral through `function`, which must be one of `Alt0` to
+    /// `Alt5`. On error, the pin is released.
+    pub fn into_alt(self, function: Function) -> Result<Pin<mode::Alt>> {
+        match function {
+            Function::Input | Function::Output => Err(Error::UnsupportedFunction(self.number)),
+            _ => Ok(self.into_mode(function)),
+        }
+    }
+
+    /// Connect the pin to its PWM channel. Only GPIO 12, 13, 18, 19, 40, 41 and 45 have one. On
+    /// error, the pin is released.
+    pub fn into_pwm(self) -> Result<Pin<mode::Pwm>> {
+        match pwm_function(self.number) {
+            Some(function) => Ok(self.into_mode(function)),
+            None => Err(Error::UnsupportedFunction(self.number)),
+        }
+    }
+
+    /// Give the pin back, leaving its function as it is.
+    pub fn release(self) {}
+
+    fn into_mode<NewMode>(self, function: Function) -> Pin<NewMode> {
+        self.gpio.inner.lock().set_function(self.number, function);
+
+        let pin = Pin {
+            number: self.number,
+            gpio: self.gpio,
+            mode: PhantomData,
         };
+        // The claim moves to the new `Pin`.
+        mem::forget(self);
+        pin
+    }
+}
 
-        let inner = &self.inner.lock();
-        inner.GPPUD.set(pull);
-        arch::spin_for_cycles(150);
+impl<Mode> Drop for Pin<Mode> {
+    fn drop(&mut self) {
+        self.gpio.inner.lock().claimed &= !(1 << self.number);
+    }
+}
+
+#[allow(dead_code)]
+impl Pin<mode::Input> {
+    pub fn is_high(&self) -> bool {
+        self.gpio.inner.lock().level(self.number)
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+#[allow(dead_code)]
+impl Pin<mode::Output> {
+    pub fn set_high(&mut self) {
+        self.gpio.inner.lock().set_level(self.number, true);
+    }
+
+    pub fn set_low(&mut self) {
+        self.gpio.inner.lock().set_level(self.number, false);
+    }
+
+    /// Whether the pin is driven high.
+    pub fn is_set_high(&self) -> bool {
+        self.gpio.inner.lock().level(self.number)
+    }
+
+    pub fn toggle(&mut self) {
+        let inner = self.gpio.inner.lock();
+        let high = inner.level(self.number);
+
+        inner.set_level(self.number, !high);
+    }
+}
+
+#[allow(dead_code)]
+impl Pin<mode::Pwm> {
+    /// The PWM channel the pin outputs.
+    pub fn channel(&self) -> Channel {
+        match self.number {
+            12 | 18 | 40 => Channel::Ch1,
+            _ => Channel::Ch2,
+        }
+    }
+}
+
+impl hal::OutputPin for Pin<mode::Output> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> core::result::Result<(), Infallible> {
+        Pin::set_low(self);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> core::result::Result<(), Infallible> {
+        Pin::set_high(self);
+        Ok(())
+    }
+}
+
+impl hal::StatefulOutputPin for Pin<mode::Output> {
+    fn is_set_high(&self) -> core::result::Result<bool, Infallible> {
+        Ok(Pin::is_set_high(self))
+    }
+
+    fn is_set_low(&self) -> core::result::Result<bool, Infallible> {
+        Ok(!Pin::is_set_high(self))
+    }
+}
 
-        let modified = (inner.GPPUDCLK0.get() as u32) | (1 << pin);
-        inner.GPPUDCLK0.set(modified);
-        arch::spin_for_cycles(150);
+impl hal::InputPin for Pin<mode::Input> {
+    type Error = Infallible;
 
-        inner.GPPUD.set(0);
-        inner.GPPUDCLK0.set(0);
+    fn is_high(&self) -> core::result::Result<bool, Infallible> {
+        Ok(Pin::is_high(self))
     }
 
-    fn setup(&self, pin: u32, direction: Dir, pud: Pud) {
-        self.pullupdn(pin, pud);
+    fn is_low(&self) -> core::result::Result<bool, Infallible> {
+        Ok(Pin::is_low(self))
+    }
+}
 
+impl interface::gpio::Set for GPIO {
+    fn pullupdn(&self, pin: u32, pud: Pud) -> Result<()> {
         let inner = &self.inner.lock();
-        let d = match direction {
-            Dir::Input => 0,
-            Dir::Output => 1,
-        };
-        match pin {
-            0..10 => {
-                inner.GPFSEL0.set(
-                    (inner.GPFSEL0.get() & 0xFFFFFFF8_u32.rotate_left(pin * 3)) | (d << (pin * 3)),
-                );
-            }
-            10..20 => {
-                inner.GPFSEL1.set(
-                    (inner.GPFSEL1.get() & 0xFFFFFFF8_u32.rotate_left((pin - 10) * 3))
-                        | (d << ((pin - 10) * 3)),
-                );
-            }
-            20..28 => {
-                inner.GPFSEL2.set(
-                    (inner.GPFSEL2.get() & 0xFFFFFFF8_u32.rotate_left((pin - 20) * 3))
-                        | (d << ((pin - 20) * 3)),
-                );
-            }
-            _ => {
-                arch::spin_for_cycles(1);
-            }
-        };
-        inner.GPCLR0.set(1 << pin);
+        let pin = inner.check_unclaimed(pin)?;
+
+        inner.set_pull(1 << pin, &pud);
+        Ok(())
     }
 
-    fn setup_pwm(&self, pin: u32) {
+    fn setup(&self, pin: u32, direction: Dir, pud: Pud) -> Result<()> {
         let inner = &self.inner.lock();
-        if pin == 12 {
-            inner.GPFSEL1.modify(GPFSEL1::FSEL12::AltFunc0);
-        } else if pin == 13 {
-            inner.GPFSEL1.modify(GPFSEL1::FSEL13::AltFunc0);
-        } else if pin == 18 {
-            inner.GPFSEL1.modify(GPFSEL1::FSEL18::AltFunc5);
-        } else if pin == 19 {
-            inner.GPFSEL1.modify(GPFSEL1::FSEL19::AltFunc5);
-        } else {
-            return;
+        let pin = inner.check_unclaimed(pin)?;
+
+        inner.set_pull(1 << pin, &pud);
+        match direction {
+            Dir::Input => inner.set_function(pin, Function::Input),
+            Dir::Output => {
+                // Latch the level first, so that the pin starts out low.
+                inner.set_level(pin, false);
+                inner.set_function(pin, Function::Output);
+            }
         }
-        arch::timer().spin_for(Duration::from_secs_f32(0.11));
-        bsp::pwm().set_mode(1);
-        bsp::pwm().set_range(1024);
-        bsp::pwm().set_clock(32);
+
+        Ok(())
+    }
+
+    fn set_function(&self, pin: u32, function: Function) -> Result<()> {
+        let inner = &self.inner.lock();
+        let pin = inner.check_unclaimed(pin)?;
+
+        inner.set_function(pin, function);
+        Ok(())
+    }
+
+    fn setup_pwm(&self, pin: u32) -> Result<()> {
+        let inner = &self.inner.lock();
+        let pin = inner.check_unclaimed(pin)?;
+        let function = pwm_function(pin).ok_or(Error::UnsupportedFunction(pin))?;
+
+        inner.set_function(pin, function);
+        Ok(())
     }
 
     fn cleanup(&self) {
         let inner = &self.inner.lock();
-        inner.GPCLR0.set(0xFFFFFFFF_u32);
+        let pins = ((1 << NUM_PINS) - 1) & !inner.claimed;
+
+        inner.GPCLR[0].set(pins as u32);
+        inner.GPCLR[1].set((pins >> 32) as u32);
     }
 }
+
 impl interface::gpio::Output for GPIO {
-    fn output(&self, pin: u32, value: u32) {
+    fn output(&self, pin: u32, value: u32) -> Result<()> {
         let inner = &self.inner.lock();
-        if value == 0 {
-            inner.GPCLR0.set(1 << pin);
-        } else {
-            inner.GPSET0.set(1 << pin);
-        }
+        let pin = inner.check_unclaimed(pin)?;
+
+        inner.set_level(pin, value != 0);
+        Ok(())
     }
 }
 
 impl interface::gpio::Input for GPIO {
-    fn input(&self, pin: u32) -> u32 {
+    fn input(&self, pin: u32) -> Result<u32> {
+        let pin = check_pin(pin)?;
         let inner = &self.inner.lock();
-        ((inner.GPLEV0.get() as u32) >> pin) & 1
+
+        Ok(inner.level(pin) as u32)
     }
 }
 
 impl interface::driver::DeviceDriver for GPIO {
     fn compatible(&self) -> &str {
-        "GPIO"
+        COMPATIBLE
+    }
+
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        self.inner.get_mut().phys_base_addr = phys_base_addr;
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        Ok(())
     }
 }
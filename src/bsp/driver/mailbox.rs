@@ -0,0 +1,633 @@
+//! Driver for the VideoCore mailbox and the firmware's property interface.
+//!
+//! The ARM hands the bus address of a message buffer to the firmware through mailbox 1 and reads
+//! the reply from mailbox 0. A property message is a list of tags, each asking for one board fact
+//! or setting, and the firmware overwrites the values of every tag with its response.
+//!
+//! The message buffer is a DMA-coherent buffer, so it needs no cache maintenance, only barriers
+//! around the mailbox accesses.
+
+use crate::{
+    arch,
+    arch::Mutex,
+    interface,
+    interface::time::Timer,
+    memory::{
+        dma::{self, DMABuffer},
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use core::{fmt, marker::PhantomData, mem, ptr, time::Duration};
+use register::mmio::{ReadOnly, WriteOnly};
+use register::{register_bitfields, register_structs};
+
+register_bitfields! {
+    u32,
+
+    /// Mailbox Status Register
+    STATUS [
+        FULL OFFSET(31) NUMBITS(1) [],
+        EMPTY OFFSET(30) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        /// Mailbox 0, VideoCore to ARM.
+        (0x00 => READ: ReadOnly<u32>),
+        (0x04 => _reserved1),
+        (0x18 => STATUS0: ReadOnly<u32, STATUS::Register>),
+        (0x1C => _reserved2),
+        /// Mailbox 1, ARM to VideoCore.
+        (0x20 => WRITE: WriteOnly<u32>),
+        (0x24 => _reserved3),
+        (0x38 => STATUS1: ReadOnly<u32, STATUS::Register>),
+        (0x3C => @END),
+    }
+}
+
+/// The channel of the property interface, ARM to VideoCore.
+const PROPERTY_CHANNEL: u32 = 8;
+
+/// The low four bits of a mailbox word select the channel, the rest is the buffer address.
+const CHANNEL_MASK: u32 = 0xF;
+
+/// Request and tag codes with this bit set are responses.
+const RESPONSE_BIT: u32 = 0x8000_0000;
+
+/// The message code of a successful reply. `0x8000_0001` means the firmware failed to parse it.
+const RESPONSE_SUCCESS: u32 = RESPONSE_BIT;
+
+/// Size of the message buffer in words.
+const MAX_MESSAGE_WORDS: usize = 64;
+
+/// The firmware requires the message buffer to be 16-byte aligned.
+const MESSAGE_ALIGN: usize = 16;
+
+/// How long to wait for the firmware before giving up.
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Reasons a property request failed.
+#[derive(Copy, Clone, Debug)]
+pub enum MailboxError {
+    /// The driver has not been initialized.
+    NotInitialized,
+    /// The tags do not fit into the message buffer.
+    MessageTooLong,
+    /// The firmware did not answer in time.
+    Timeout,
+    /// The firmware could not parse the message.
+    RequestFailed,
+    /// The firmware did not process the tag with the given ID, e.g. because it does not know it.
+    TagNotProcessed(u32),
+    /// The response of the tag with the given ID is shorter than expected.
+    ResponseTooShort(u32),
+}
+
+impl fmt::Display for MailboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MailboxError::NotInitialized => f.write_str("Mailbox not initialized"),
+            MailboxError::MessageTooLong => f.write_str("Message too long"),
+            MailboxError::Timeout => f.write_str("Firmware did not answer"),
+            MailboxError::RequestFailed => f.write_str("Firmware could not parse the message"),
+            MailboxError::TagNotProcessed(id) => write!(f, "Tag {:#010x} not processed", id),
+            MailboxError::ResponseTooShort(id) => {
+                write!(f, "Tag {:#010x}: response too short", id)
+            }
+        }
+    }
+}
+
+/// A property tag: its ID, its request values and how to read its response.
+pub trait Tag {
+    const ID: u32;
+
+    /// Size of the tag's value buffer in words, large enough for request and response.
+    const VALUE_WORDS: usize;
+
+    /// Number of words the response must have for `response()`.
+    const RESPONSE_WORDS: usize;
+
+    type Response;
+
+    /// Write the request values into the zeroed value buffer.
+    fn request(&self, _values: &mut [u32]) {}
+
+    /// Read the response from the value buffer the firmware wrote.
+    fn response(values: &[u32]) -> Self::Response;
+}
+
+/// The clocks known to the firmware.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+#[repr(u32)]
+pub enum ClockId {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Hevc = 11,
+    Emmc2 = 12,
+    M2mc = 13,
+    PixelBvb = 14,
+}
+
+/// The power domains the firmware switches.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+#[repr(u32)]
+pub enum PowerDevice {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+/// State of a power domain.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub struct PowerState {
+    pub on: bool,
+    pub exists: bool,
+}
+
+impl PowerState {
+    fn from_word(word: u32) -> Self {
+        PowerState {
+            on: word & 0b01 != 0,
+            exists: word & 0b10 == 0,
+        }
+    }
+}
+
+/// A range of physical memory, as reported by the firmware.
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryRegion {
+    pub base: u32,
+    pub size: u32,
+}
+
+pub struct GetFirmwareRevision;
+
+impl Tag for GetFirmwareRevision {
+    const ID: u32 = 0x0000_0001;
+    const VALUE_WORDS: usize = 1;
+    const RESPONSE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[0]
+    }
+}
+
+pub struct GetBoardModel;
+
+impl Tag for GetBoardModel {
+    const ID: u32 = 0x0001_0001;
+    const VALUE_WORDS: usize = 1;
+    const RESPONSE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[0]
+    }
+}
+
+pub struct GetBoardRevision;
+
+impl Tag for GetBoardRevision {
+    const ID: u32 = 0x0001_0002;
+    const VALUE_WORDS: usize = 1;
+    const RESPONSE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[0]
+    }
+}
+
+pub struct GetBoardSerial;
+
+impl Tag for GetBoardSerial {
+    const ID: u32 = 0x0001_0004;
+    const VALUE_WORDS: usize = 2;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = u64;
+
+    fn response(values: &[u32]) -> u64 {
+        (u64::from(values[1]) << 32) | u64::from(values[0])
+    }
+}
+
+/// The part of the RAM the ARM cores own.
+pub struct GetArmMemory;
+
+impl Tag for GetArmMemory {
+    const ID: u32 = 0x0001_0005;
+    const VALUE_WORDS: usize = 2;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = MemoryRegion;
+
+    fn response(values: &[u32]) -> MemoryRegion {
+        MemoryRegion {
+            base: values[0],
+            size: values[1],
+        }
+    }
+}
+
+/// The part of the RAM the VideoCore keeps for itself.
+pub struct GetVcMemory;
+
+impl Tag for GetVcMemory {
+    const ID: u32 = 0x0001_0006;
+    const VALUE_WORDS: usize = 2;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = MemoryRegion;
+
+    fn response(values: &[u32]) -> MemoryRegion {
+        GetArmMemory::response(values)
+    }
+}
+
+pub struct GetPowerState(pub PowerDevice);
+
+impl Tag for GetPowerState {
+    const ID: u32 = 0x0002_0001;
+    const VALUE_WORDS: usize = 2;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = PowerState;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.0 as u32;
+    }
+
+    fn response(values: &[u32]) -> PowerState {
+        PowerState::from_word(values[1])
+    }
+}
+
+/// Switch a power domain, waiting for it to become stable if `wait` is set.
+pub struct SetPowerState {
+    pub device: PowerDevice,
+    pub on: bool,
+    pub wait: bool,
+}
+
+impl Tag for SetPowerState {
+    const ID: u32 = 0x0002_8001;
+    const VALUE_WORDS: usize = 2;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = PowerState;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.device as u32;
+        values[1] = (self.on as u32) | ((self.wait as u32) << 1);
+    }
+
+    fn response(values: &[u32]) -> PowerState {
+        PowerState::from_word(values[1])
+    }
+}
+
+/// The rate of a clock in Hz. 0 if the clock does not exist.
+pub struct GetClockRate(pub ClockId);
+
+impl Tag for GetClockRate {
+    const ID: u32 = 0x0003_0002;
+    const VALUE_WORDS: usize = 2;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = u32;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.0 as u32;
+    }
+
+    fn response(values: &[u32]) -> u32 {
+        values[1]
+    }
+}
+
+/// Set the rate of a clock in Hz. The response is the rate the firmware actually chose.
+pub struct SetClockRate {
+    pub clock: ClockId,
+    pub rate_hz: u32,
+    /// Do not raise the other clocks to their turbo settings along with the ARM clock.
+    pub skip_setting_turbo: bool,
+}
+
+impl Tag for SetClockRate {
+    const ID: u32 = 0x0003_8002;
+    const VALUE_WORDS: usize = 3;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = u32;
+
+    fn request(&self, values: &mut [u32]) {
+        values[0] = self.clock as u32;
+        values[1] = self.rate_hz;
+        values[2] = self.skip_setting_turbo as u32;
+    }
+
+    fn response(values: &[u32]) -> u32 {
+        values[1]
+    }
+}
+
+/// The SoC temperature in thousandths of a degree Celsius.
+pub struct GetTemperature;
+
+impl Tag for GetTemperature {
+    const ID: u32 = 0x0003_0006;
+    const VALUE_WORDS: usize = 2;
+    const RESPONSE_WORDS: usize = 2;
+    type Response = u32;
+
+    fn response(values: &[u32]) -> u32 {
+        values[1]
+    }
+}
+
+/// Where the values of a tag of type `T` are in a `PropertyMessage`.
+pub struct TagSlot<T: Tag> {
+    offset: usize,
+    tag: PhantomData<T>,
+}
+
+/// A property message under construction, and after `Mailbox::call()` the firmware's reply.
+pub struct PropertyMessage {
+    words: [u32; MAX_MESSAGE_WORDS],
+    /// Words in use: the two header words and the tags added so far.
+    len: usize,
+}
+
+/// Words of a tag besides its values: ID, value buffer size and request/response code.
+const TAG_HEADER_WORDS: usize = 3;
+
+#[allow(dead_code)]
+impl PropertyMessage {
+    pub fn new() -> Self {
+        PropertyMessage {
+            words: [0; MAX_MESSAGE_WORDS],
+            len: 2,
+        }
+    }
+
+    /// Append a tag. The slot reads its response once the message was sent.
+    pub fn add<T: Tag>(&mut self, tag: &T) -> Result<TagSlot<T>, MailboxError> {
+        let offset = self.len;
+        let values = offset + TAG_HEADER_WORDS;
+
+        // Leave room for the end tag.
+        if values + T::VALUE_WORDS + 1 > MAX_MESSAGE_WORDS {
+            return Err(MailboxError::MessageTooLong);
+        }
+
+        self.words[offset] = T::ID;
+        self.words[offset + 1] = (T::VALUE_WORDS * 4) as u32;
+        self.words[offset + 2] = 0;
+        tag.request(&mut self.words[values..values + T::VALUE_WORDS]);
+        self.len = values + T::VALUE_WORDS;
+
+        Ok(TagSlot {
+            offset,
+            tag: PhantomData,
+        })
+    }
+
+    /// The firmware's response to the tag in `slot`.
+    pub fn response<T: Tag>(&self, slot: &TagSlot<T>) -> Result<T::Response, MailboxError> {
+        let code = self.words[slot.offset + 2];
+        if code & RESPONSE_BIT == 0 {
+            return Err(MailboxError::TagNotProcessed(T::ID));
+        }
+        if ((code & !RESPONSE_BIT) as usize) < T::RESPONSE_WORDS * 4 {
+            return Err(MailboxError::ResponseTooShort(T::ID));
+        }
+
+        let values = slot.offset + TAG_HEADER_WORDS;
+        Ok(T::response(&self.words[values..values + T::VALUE_WORDS]))
+    }
+
+    /// Terminate the message and fill in its header. Returns the words to send, padded to the
+    /// buffer alignment.
+    fn finish(&mut self) -> &[u32] {
+        let align_words = MESSAGE_ALIGN / 4;
+        let total = (self.len + 1 + align_words - 1) & !(align_words - 1);
+
+        // End tag and padding.
+        for word in &mut self.words[self.len..total] {
+            *word = 0;
+        }
+        self.words[0] = (total * 4) as u32;
+        self.words[1] = 0;
+
+        &self.words[..total]
+    }
+}
+
+struct MailboxInner {
+    phys_base_addr: usize,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
+    buffer: Option<DMABuffer>,
+}
+
+impl MailboxInner {
+    const fn new(phys_base_addr: usize) -> MailboxInner {
+        MailboxInner {
+            phys_base_addr,
+            mmio: None,
+            buffer: None,
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
+    }
+
+    fn registers(&self) -> Option<&RegisterBlock> {
+        self.mmio.as_ref().map(|mmio| unsafe { &*mmio.ptr() })
+    }
+
+    /// Send `message` on the property channel and copy the reply back into it.
+    fn call(&self, message: &mut PropertyMessage) -> Result<(), MailboxError> {
+        let (regs, buffer) = match (self.registers(), self.buffer.as_ref()) {
+            (Some(regs), Some(buffer)) => (regs, buffer),
+            _ => return Err(MailboxError::NotInitialized),
+        };
+        let words = message.finish();
+        let len = words.len();
+        let ptr = buffer.as_mut_ptr() as *mut u32;
+
+        for (i, &word) in words.iter().enumerate() {
+            unsafe { ptr::write_volatile(ptr.add(i), word) };
+        }
+        // The message must be in memory before the firmware learns about it.
+        arch::cache::data_sync_barrier();
+
+        wait_while(|| regs.STATUS1.is_set(STATUS::FULL))?;
+        regs.WRITE.set(buffer.bus_addr() as u32 | PROPERTY_CHANNEL);
+
+        // Replies on other channels are not ours.
+        loop {
+            wait_while(|| regs.STATUS0.is_set(STATUS::EMPTY))?;
+            if regs.READ.get() & CHANNEL_MASK == PROPERTY_CHANNEL {
+                break;
+            }
+        }
+        arch::cache::data_sync_barrier();
+
+        for (i, word) in message.words[..len].iter_mut().enumerate() {
+            *word = unsafe { ptr::read_volatile(ptr.add(i)) };
+        }
+
+        if message.words[1] != RESPONSE_SUCCESS {
+            return Err(MailboxError::RequestFailed);
+        }
+
+        Ok(())
+    }
+}
+
+/// Spin while `condition` holds, for at most `TIMEOUT`.
+fn wait_while(condition: impl Fn() -> bool) -> Result<(), MailboxError> {
+    let deadline = arch::timer().uptime() + TIMEOUT;
+
+    while condition() {
+        if arch::timer().uptime() > deadline {
+            return Err(MailboxError::Timeout);
+        }
+    }
+
+    Ok(())
+}
+
+pub struct Mailbox {
+    inner: Mutex<MailboxInner>,
+}
+
+#[allow(dead_code)]
+impl Mailbox {
+    /// Create an instance for the registers at the physical address `phys_base_addr`.
+    pub const unsafe fn new(phys_base_addr: usize) -> Mailbox {
+        Mailbox {
+            inner: Mutex::new(MailboxInner::new(phys_base_addr)),
+        }
+    }
+
+    /// Send `message` to the firmware and wait for the reply, which replaces the message.
+    pub fn call(&self, message: &mut PropertyMessage) -> Result<(), MailboxError> {
+        self.inner.lock().call(message)
+    }
+
+    /// Send a message holding only `tag` and return its response.
+    pub fn request<T: Tag>(&self, tag: &T) -> Result<T::Response, MailboxError> {
+        let mut message = PropertyMessage::new();
+        let slot = message.add(tag)?;
+
+        self.call(&mut message)?;
+        message.response(&slot)
+    }
+
+    pub fn get_firmware_revision(&self) -> Result<u32, MailboxError> {
+        self.request(&GetFirmwareRevision)
+    }
+
+    pub fn get_board_model(&self) -> Result<u32, MailboxError> {
+        self.request(&GetBoardModel)
+    }
+
+    pub fn get_board_revision(&self) -> Result<u32, MailboxError> {
+        self.request(&GetBoardRevision)
+    }
+
+    pub fn get_board_serial(&self) -> Result<u64, MailboxError> {
+        self.request(&GetBoardSerial)
+    }
+
+    pub fn get_arm_memory(&self) -> Result<MemoryRegion, MailboxError> {
+        self.request(&GetArmMemory)
+    }
+
+    pub fn get_vc_memory(&self) -> Result<MemoryRegion, MailboxError> {
+        self.request(&GetVcMemory)
+    }
+
+    pub fn get_power_state(&self, device: PowerDevice) -> Result<PowerState, MailboxError> {
+        self.request(&GetPowerState(device))
+    }
+
+    /// Switch `device` on or off and wait until it is stable.
+    pub fn set_power_state(
+        &self,
+        device: PowerDevice,
+        on: bool,
+    ) -> Result<PowerState, MailboxError> {
+        self.request(&SetPowerState {
+            device,
+            on,
+            wait: true,
+        })
+    }
+
+    /// The rate of `clock` in Hz.
+    pub fn get_clock_rate(&self, clock: ClockId) -> Result<u32, MailboxError> {
+        self.request(&GetClockRate(clock))
+    }
+
+    /// Set the rate of `clock` and return the rate in Hz the firmware chose.
+    pub fn set_clock_rate(&self, clock: ClockId, rate_hz: u32) -> Result<u32, MailboxError> {
+        self.request(&SetClockRate {
+            clock,
+            rate_hz,
+            skip_setting_turbo: false,
+        })
+    }
+
+    /// The SoC temperature in thousandths of a degree Celsius.
+    pub fn get_temperature(&self) -> Result<u32, MailboxError> {
+        self.request(&GetTemperature)
+    }
+}
+
+impl interface::driver::DeviceDriver for Mailbox {
+    fn compatible(&self) -> &str {
+        "brcm,bcm2835-mbox"
+    }
+
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        self.inner.get_mut().phys_base_addr = phys_base_addr;
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        if inner.buffer.is_none() {
+            let buffer = dma::alloc(MAX_MESSAGE_WORDS * 4, MESSAGE_ALIGN);
+            let error = interface::driver::Error::Device("No DMA memory for messages");
+            inner.buffer = Some(buffer.ok_or(error)?);
+        }
+
+        Ok(())
+    }
+}
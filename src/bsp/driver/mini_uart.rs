@@ -0,0 +1,387 @@
+//! Driver for the mini UART of the BCM2837 auxiliary peripherals (AUX).
+//!
+//! The mini UART is clocked by the VPU core clock, so its baud rate follows the core frequency.
+//! The firmware keeps the core clock fixed when `config.txt` has `enable_uart=1`.
+
+use crate::{
+    arch::Mutex,
+    interface,
+    memory::{
+        self,
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use asm::nop;
+use core::{convert::Infallible, fmt, mem, ops};
+use cortex_a::asm;
+use embedded_hal::{blocking, serial};
+use register::{mmio::*, register_bitfields, register_structs};
+use spin::Once;
+
+register_bitfields! {
+    u32,
+
+    /// Auxiliary Enables
+    AUX_ENABLES [
+        MINI_UART OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Mini UART Interrupt Identify
+    AUX_MU_IIR [
+        FIFO_CLEAR OFFSET(1) NUMBITS(2) [
+            Rx = 0b01,
+            Tx = 0b10,
+            All = 0b11
+        ]
+    ],
+
+    /// Mini UART Line Control
+    AUX_MU_LCR [
+        DATA_SIZE OFFSET(0) NUMBITS(2) [
+            SevenBit = 0b00,
+            EightBit = 0b11
+        ]
+    ],
+
+    /// Mini UART Line Status
+    AUX_MU_LSR [
+        TX_IDLE OFFSET(6) NUMBITS(1) [],
+        TX_EMPTY OFFSET(5) NUMBITS(1) [],
+        DATA_READY OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Extra Control
+    AUX_MU_CNTL [
+        TX_ENABLE OFFSET(1) NUMBITS(1) [],
+        RX_ENABLE OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Baudrate
+    AUX_MU_BAUD [
+        BAUDRATE OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub RegisterBlock {
+        (0x00 => _reserved1),
+        (0x04 => AUX_ENABLES: ReadWrite<u32, AUX_ENABLES::Register>),
+        (0x08 => _reserved2),
+        (0x40 => AUX_MU_IO: ReadWrite<u32>),
+        (0x44 => AUX_MU_IER: WriteOnly<u32>),
+        (0x48 => AUX_MU_IIR: WriteOnly<u32, AUX_MU_IIR::Register>),
+        (0x4C => AUX_MU_LCR: WriteOnly<u32, AUX_MU_LCR::Register>),
+        (0x50 => AUX_MU_MCR: WriteOnly<u32>),
+        (0x54 => AUX_MU_LSR: ReadOnly<u32, AUX_MU_LSR::Register>),
+        (0x58 => _reserved3),
+        (0x60 => AUX_MU_CNTL: WriteOnly<u32, AUX_MU_CNTL::Register>),
+        (0x64 => _reserved4),
+        (0x68 => AUX_MU_BAUD: WriteOnly<u32, AUX_MU_BAUD::Register>),
+        (0x6C => @END),
+    }
+}
+
+/// Offset of the mini UART registers in the AUX block. The device tree describes only these, the
+/// AUX enables lie below them.
+const MINI_UART_OFFSET: usize = 0x40;
+
+/// Same rate as the PL011.
+const BAUD_RATE: u32 = 230_400;
+
+pub struct MiniUartInner {
+    phys_base_addr: usize,
+    core_clock_hz: u32,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
+    chars_written: usize,
+    chars_read: usize,
+}
+
+impl ops::Deref for MiniUartInner {
+    type Target = RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr() }
+    }
+}
+
+impl MiniUartInner {
+    pub const unsafe fn new(phys_base_addr: usize, core_clock_hz: u32) -> MiniUartInner {
+        MiniUartInner {
+            phys_base_addr,
+            core_clock_hz,
+            mmio: None,
+            chars_written: 0,
+            chars_read: 0,
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
+    }
+
+    /// 8N1 at `BAUD_RATE`, without interrupts or flow control.
+    pub fn init(&self) {
+        // The other bits enable the SPI masters, which share the AUX block.
+        self.AUX_ENABLES.modify(AUX_ENABLES::MINI_UART::Enabled);
+
+        self.AUX_MU_CNTL.set(0);
+        self.AUX_MU_IER.set(0);
+        self.AUX_MU_LCR.write(AUX_MU_LCR::DATA_SIZE::EightBit);
+        self.AUX_MU_MCR.set(0);
+        self.AUX_MU_IIR.write(AUX_MU_IIR::FIFO_CLEAR::All);
+
+        // baud = core_clock / (8 * (BAUDRATE + 1)), rounded to the nearest divisor.
+        let divisor = (self.core_clock_hz + 4 * BAUD_RATE) / (8 * BAUD_RATE) - 1;
+        self.AUX_MU_BAUD.write(AUX_MU_BAUD::BAUDRATE.val(divisor));
+
+        self.AUX_MU_CNTL
+            .write(AUX_MU_CNTL::TX_ENABLE::SET + AUX_MU_CNTL::RX_ENABLE::SET);
+    }
+
+    fn ptr(&self) -> *const RegisterBlock {
+        self.mmio
+            .as_ref()
+            .expect("Mini UART registers are not mapped")
+            .ptr()
+    }
+
+    fn write_char(&mut self, c: char) {
+        // Console output from before `init()`, or after it failed, is dropped.
+        if self.mmio.is_none() {
+            return;
+        }
+        if c == '\n' {
+            self.write_char('\r');
+        }
+        while !self.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_EMPTY) {
+            nop();
+        }
+
+        self.AUX_MU_IO.set(c as u32);
+        self.chars_written += 1;
+    }
+}
+
+impl fmt::Write for MiniUartInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct MiniUart {
+    phys_base_addr: usize,
+    core_clock_hz: u32,
+    inner: Mutex<MiniUartInner>,
+    /// Copy of the register mapping, readable without taking the lock.
+    mmio: Once<MMIOMapping<RegisterBlock>>,
+}
+
+#[allow(dead_code)]
+impl MiniUart {
+    /// Create an instance for the AUX registers at the physical address `phys_base_addr`, clocked
+    /// by a core clock of `core_clock_hz`.
+    pub const unsafe fn new(phys_base_addr: usize, core_clock_hz: u32) -> MiniUart {
+        MiniUart {
+            phys_base_addr,
+            core_clock_hz,
+            inner: Mutex::new(MiniUartInner::new(phys_base_addr, core_clock_hz)),
+            mmio: Once::new(),
+        }
+    }
+
+    /// Map the registers for `panic_uart()` ahead of `init()`. Populating the page tables from the
+    /// virtual memory layout drops the boot mapping's alias of them.
+    ///
+    /// # Safety
+    ///
+    /// - The page tables must have been populated from the virtual memory layout.
+    pub unsafe fn map_panic_uart(&self) -> Result<(), MapError> {
+        let mut inner = self.inner.lock();
+        inner.map_mmio()?;
+
+        if let Some(mmio) = inner.mmio {
+            self.mmio.call_once(|| mmio);
+        }
+
+        Ok(())
+    }
+
+    /// An instance for the panic handler that does not share the lock.
+    ///
+    /// Uses the mapping set up by `map_panic_uart()` or `init()`. Before that, the boot mapping
+    /// still maps the MMIO window linearly into the upper half.
+    pub unsafe fn panic_uart(&self) -> PanicMiniUart {
+        let mmio = match self.mmio.r#try() {
+            Some(mmio) => *mmio,
+            None => MMIOMapping::new(
+                self.phys_base_addr,
+                memory::kernel_phys_to_virt(self.phys_base_addr),
+                mem::size_of::<RegisterBlock>(),
+            ),
+        };
+
+        let mut uart = PanicMiniUart::new(self.phys_base_addr, self.core_clock_hz);
+        uart.mmio = Some(mmio);
+        uart
+    }
+}
+
+impl interface::driver::DeviceDriver for MiniUart {
+    fn compatible(&self) -> &str {
+        "brcm,bcm2835-aux-uart"
+    }
+
+    /// `phys_base_addr` is that of the mini UART registers, as the device tree gives it. An address
+    /// with no room for the AUX block below it is ignored.
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        let aux_base_addr = match phys_base_addr.checked_sub(MINI_UART_OFFSET) {
+            Some(addr) => addr,
+            None => return,
+        };
+        if aux_base_addr != self.phys_base_addr {
+            // Drop the registers mapped early by `map_panic_uart()`.
+            self.mmio = Once::new();
+            self.inner.get_mut().mmio = None;
+        }
+
+        self.phys_base_addr = aux_base_addr;
+        self.inner.get_mut().phys_base_addr = aux_base_addr;
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        if let Some(mmio) = inner.mmio {
+            self.mmio.call_once(|| mmio);
+        }
+        inner.init();
+
+        Ok(())
+    }
+}
+
+impl interface::console::Write for MiniUart {
+    fn write_char(&self, c: char) {
+        let mut inner = self.inner.lock();
+        inner.write_char(c);
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        let mut inner = self.inner.lock();
+        fmt::Write::write_fmt(&mut *inner, args)
+    }
+
+    fn flush(&self) {
+        let inner = self.inner.lock();
+        if inner.mmio.is_none() {
+            return;
+        }
+        // Spin until the transmitter is idle and its FIFO empty.
+        while !inner.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_IDLE) {
+            nop();
+        }
+    }
+}
+
+impl interface::console::Read for MiniUart {
+    fn read_char(&self) -> char {
+        let mut inner = self.inner.lock();
+        // Spin until a character has arrived.
+        while !inner.AUX_MU_LSR.is_set(AUX_MU_LSR::DATA_READY) {
+            nop();
+        }
+
+        // Read one character.
+        let mut ret = inner.AUX_MU_IO.get() as u8 as char;
+
+        // Convert carrige return to newline.
+        if ret == '\r' {
+            ret = '\n'
+        }
+
+        // Update statistics.
+        inner.chars_read += 1;
+
+        ret
+    }
+
+    fn clear(&self) {
+        let inner = self.inner.lock();
+        // Read from the RX FIFO until it is empty.
+        while inner.AUX_MU_LSR.is_set(AUX_MU_LSR::DATA_READY) {
+            inner.AUX_MU_IO.get();
+        }
+    }
+}
+
+/// Raw bytes, without the console's newline conversion.
+impl serial::Read<u8> for MiniUart {
+    type Error = Infallible;
+
+    fn read(&mut self) -> nb::Result<u8, Infallible> {
+        let mut inner = self.inner.lock();
+        if !inner.AUX_MU_LSR.is_set(AUX_MU_LSR::DATA_READY) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        inner.chars_read += 1;
+        Ok(inner.AUX_MU_IO.get() as u8)
+    }
+}
+
+impl serial::Write<u8> for MiniUart {
+    type Error = Infallible;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+        let mut inner = self.inner.lock();
+        if !inner.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_EMPTY) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        inner.AUX_MU_IO.set(word.into());
+        inner.chars_written += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        let inner = self.inner.lock();
+        if inner.AUX_MU_LSR.is_set(AUX_MU_LSR::TX_IDLE) {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// The mini UART has no DMA request line, so blocking writes poll `serial::Write`.
+impl blocking::serial::write::Default<u8> for MiniUart {}
+
+impl interface::console::Statistics for MiniUart {
+    fn chars_written(&self) -> usize {
+        let inner = self.inner.lock();
+        inner.chars_written
+    }
+
+    fn chars_read(&self) -> usize {
+        let inner = self.inner.lock();
+        inner.chars_read
+    }
+}
+
+pub use MiniUartInner as PanicMiniUart;
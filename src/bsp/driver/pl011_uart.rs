@@ -1,8 +1,19 @@
-use crate::{arch::Mutex, interface};
+use crate::{
+    arch::Mutex,
+    interface,
+    interface::dma::{Endpoint, Segment},
+    memory::{
+        self, dma,
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
 use asm::nop;
-use core::{fmt, ops};
+use core::{convert::Infallible, fmt, mem, ops, ptr, time::Duration};
 use cortex_a::asm;
+use embedded_hal::{blocking, serial};
 use register::{mmio::*, register_bitfields, register_structs};
+use spin::Once;
 
 register_bitfields! {
     u32,
@@ -54,6 +65,13 @@ register_bitfields! {
 
     ICR [
         ALL OFFSET(0) NUMBITS(11) []
+    ],
+
+    DMACR [
+        TXDMAE OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
     ]
 }
 
@@ -70,12 +88,24 @@ register_structs! {
         (0x30 => CR: WriteOnly<u32, CR::Register>),
         (0x34 => _reserved3),
         (0x44 => ICR: WriteOnly<u32, ICR::Register>),
-        (0x48 => @END),
+        (0x48 => DMACR: WriteOnly<u32, DMACR::Register>),
+        (0x4C => @END),
     }
 }
 
+/// The BCM2837's DMA request line for the TX FIFO.
+const DREQ_TX: u32 = 12;
+
+/// Characters the TX FIFO holds.
+const TX_FIFO_LEN: usize = 16;
+
+/// How long a byte takes on the wire: 10 bit times at the 230400 baud `init()` sets up for a
+/// 48 MHz UART clock.
+const BYTE_TIME: Duration = Duration::from_micros(44);
+
 pub struct PL011UartInner {
-    base_addr: usize,
+    phys_base_addr: usize,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
     chars_written: usize,
     chars_read: usize,
 }
@@ -89,14 +119,25 @@ impl ops::Deref for PL011UartInner {
 }
 
 impl PL011UartInner {
-    pub const unsafe fn new(base_addr: usize) -> PL011UartInner {
+    pub const unsafe fn new(phys_base_addr: usize) -> PL011UartInner {
         PL011UartInner {
-            base_addr,
+            phys_base_addr,
+            mmio: None,
             chars_written: 0,
             chars_read: 0,
         }
     }
 
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
+    }
+
     pub fn init(&self) {
         self.CR.set(0);
         self.ICR.write(ICR::ALL::CLEAR);
@@ -109,10 +150,26 @@ impl PL011UartInner {
     }
 
     fn ptr(&self) -> *const RegisterBlock {
-        self.base_addr as *const _
+        self.mmio
+            .as_ref()
+            .expect("PL011 registers are not mapped")
+            .ptr()
+    }
+
+    /// The bus address of the data register, for DMA engines.
+    fn data_bus_addr(&self) -> usize {
+        let offset = &self.DR as *const _ as usize - self.ptr() as usize;
+
+        self.mmio
+            .as_ref()
+            .map_or(0, |mmio| mmio.bus_addr() + offset)
     }
 
     fn write_char(&mut self, c: char) {
+        // Console output from before `init()`, or after it failed, is dropped.
+        if self.mmio.is_none() {
+            return;
+        }
         if c == '\n' {
             self.write_char('\r');
         }
@@ -136,24 +193,96 @@ impl fmt::Write for PL011UartInner {
 }
 
 pub struct PL011Uart {
+    phys_base_addr: usize,
     inner: Mutex<PL011UartInner>,
+    /// Copy of the register mapping, readable without taking the lock.
+    mmio: Once<MMIOMapping<RegisterBlock>>,
+    dma: Option<&'static dyn interface::dma::Engine>,
 }
 
 impl PL011Uart {
-    pub const unsafe fn new(base_addr: usize) -> PL011Uart {
+    /// Create an instance for the registers at the physical address `phys_base_addr`.
+    pub const unsafe fn new(phys_base_addr: usize) -> PL011Uart {
         PL011Uart {
-            inner: Mutex::new(PL011UartInner::new(base_addr)),
+            phys_base_addr,
+            inner: Mutex::new(PL011UartInner::new(phys_base_addr)),
+            mmio: Once::new(),
+            dma: None,
+        }
+    }
+
+    /// Send long runs of bytes written with `bwrite_all()` through `dma`.
+    #[allow(dead_code)]
+    pub fn set_dma(&mut self, dma: &'static dyn interface::dma::Engine) {
+        self.dma = Some(dma);
+    }
+
+    /// Map the registers for `panic_uart()` ahead of `init()`. Populating the page tables from the
+    /// virtual memory layout drops the boot mapping's alias of them.
+    ///
+    /// # Safety
+    ///
+    /// - The page tables must have been populated from the virtual memory layout.
+    pub unsafe fn map_panic_uart(&self) -> Result<(), MapError> {
+        let mut inner = self.inner.lock();
+        inner.map_mmio()?;
+
+        if let Some(mmio) = inner.mmio {
+            self.mmio.call_once(|| mmio);
         }
+
+        Ok(())
+    }
+
+    /// An instance for the panic handler that does not share the lock.
+    ///
+    /// Uses the mapping set up by `map_panic_uart()` or `init()`. Before that, the boot mapping
+    /// still maps the MMIO window linearly into the upper half.
+    pub unsafe fn panic_uart(&self) -> PanicUart {
+        let mmio = match self.mmio.r#try() {
+            Some(mmio) => *mmio,
+            None => MMIOMapping::new(
+                self.phys_base_addr,
+                memory::kernel_phys_to_virt(self.phys_base_addr),
+                mem::size_of::<RegisterBlock>(),
+            ),
+        };
+
+        let mut uart = PanicUart::new(self.phys_base_addr);
+        uart.mmio = Some(mmio);
+        uart
     }
 }
 
 impl interface::driver::DeviceDriver for PL011Uart {
     fn compatible(&self) -> &str {
-        "PL011Uart"
+        "arm,pl011"
+    }
+
+    /// The BCM2711's UART2 to UART5 share the `compatible`, so the compiled-in address picks the
+    /// node.
+    fn fixed_phys_base_addr(&self) -> Option<usize> {
+        Some(self.phys_base_addr)
+    }
+
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        if phys_base_addr != self.phys_base_addr {
+            // Drop the registers mapped early by `map_panic_uart()`.
+            self.mmio = Once::new();
+            self.inner.get_mut().mmio = None;
+        }
+
+        self.phys_base_addr = phys_base_addr;
+        self.inner.get_mut().phys_base_addr = phys_base_addr;
     }
 
     fn init(&self) -> interface::driver::Result {
-        let inner = self.inner.lock();
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        if let Some(mmio) = inner.mmio {
+            self.mmio.call_once(|| mmio);
+        }
         inner.init();
 
         Ok(())
@@ -173,6 +302,9 @@ impl interface::console::Write for PL011Uart {
 
     fn flush(&self) {
         let inner = self.inner.lock();
+        if inner.mmio.is_none() {
+            return;
+        }
         // Spin until TX FIFO empty is set.
         while !inner.FR.matches_all(FR::TXFE::SET) {
             nop();
@@ -211,6 +343,95 @@ impl interface::console::Read for PL011Uart {
     }
 }
 
+/// Raw bytes, without the console's newline conversion.
+impl serial::Read<u8> for PL011Uart {
+    type Error = Infallible;
+
+    fn read(&mut self) -> nb::Result<u8, Infallible> {
+        let mut inner = self.inner.lock();
+        if inner.FR.matches_all(FR::RXFE::SET) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        inner.chars_read += 1;
+        Ok(inner.DR.get() as u8)
+    }
+}
+
+impl serial::Write<u8> for PL011Uart {
+    type Error = Infallible;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+        let mut inner = self.inner.lock();
+        if inner.FR.matches_all(FR::TXFF::SET) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        inner.DR.set(word.into());
+        inner.chars_written += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        let inner = self.inner.lock();
+        if inner.FR.matches_all(FR::TXFE::SET) {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// Runs longer than the TX FIFO go through the DMA engine if there is one. Each byte is widened
+/// to a word in DMA-coherent memory, and the engine writes the words to the data register as the
+/// UART asks for them. Only the DMA engine can fail.
+impl blocking::serial::Write<u8> for PL011Uart {
+    type Error = interface::dma::Error;
+
+    fn bwrite_all(&mut self, buffer: &[u8]) -> Result<(), Self::Error> {
+        let mut inner = self.inner.lock();
+        let dma_engine = self.dma.filter(|_| buffer.len() > TX_FIFO_LEN);
+
+        if let (Some(engine), Some(words)) = (dma_engine, dma::alloc(buffer.len() * 4, 4)) {
+            let ptr = words.as_mut_ptr() as *mut u32;
+            for (i, &byte) in buffer.iter().enumerate() {
+                unsafe { ptr::write_volatile(ptr.add(i), byte.into()) };
+            }
+
+            let segment = Segment {
+                src: Endpoint::Memory(words.bus_addr()),
+                dest: Endpoint::Peripheral(inner.data_bus_addr(), DREQ_TX),
+                len: words.size(),
+            };
+            let timeout = BYTE_TIME * buffer.len() as u32 * 2 + Duration::from_millis(10);
+
+            inner.DMACR.write(DMACR::TXDMAE::Enabled);
+            let result = unsafe { engine.transfer(&[segment], timeout) };
+            inner.DMACR.write(DMACR::TXDMAE::Disabled);
+            result?;
+        } else {
+            for &byte in buffer {
+                while inner.FR.matches_all(FR::TXFF::SET) {
+                    nop();
+                }
+                inner.DR.set(byte.into());
+            }
+        }
+
+        inner.chars_written += buffer.len();
+        Ok(())
+    }
+
+    fn bflush(&mut self) -> Result<(), Self::Error> {
+        let inner = self.inner.lock();
+        while !inner.FR.matches_all(FR::TXFE::SET) {
+            nop();
+        }
+
+        Ok(())
+    }
+}
+
 impl interface::console::Statistics for PL011Uart {
     fn chars_written(&self) -> usize {
         let inner = self.inner.lock();
@@ -1,7 +1,22 @@
-use crate::bsp::driver::clock::Clock;
-use crate::{arch, arch::Mutex, interface, interface::time::Timer};
-use core::ops;
-use core::time::Duration;
+use crate::bsp::driver::{
+    clock,
+    clock::Clock,
+    gpio::{self, mode, Pin},
+};
+use crate::{
+    arch::Mutex,
+    interface,
+    interface::{
+        dma::{Endpoint, Segment},
+        pwm::{Channel, Error, Mode, Polarity, Result, StreamMode, Underrun},
+    },
+    memory::{
+        dma::{self, DMABuffer},
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use core::{cmp, mem, ops, ptr, time::Duration};
 use register::mmio::ReadWrite;
 use register::{register_bitfields, register_structs};
 
@@ -127,8 +142,24 @@ register_structs! {
     }
 }
 
+/// The fastest the PWM clock is run. The clock is divided from the clock manager's source down to
+/// this or below, and the range then sets the frequency, so at 1 kHz the duty cycle has about
+/// 10000 steps.
+const MAX_CLOCK_HZ: u32 = 10_000_000;
+
+/// The DMA request line the PWM paces transfers to its FIFO with.
+const DREQ: u32 = 5;
+
+/// Words the FIFO holds. The PWM requests more once fewer are queued.
+const FIFO_LEN: u32 = 8;
+
 struct PWMInner {
-    base_addr: usize,
+    phys_base_addr: usize,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
+    /// The PWM clock, 0 until `init()`.
+    clock_hz: u32,
+    /// The underrun policy of each channel streaming from the FIFO.
+    streams: [Option<Underrun>; 2],
 }
 
 impl ops::Deref for PWMInner {
@@ -140,78 +171,453 @@ impl ops::Deref for PWMInner {
 }
 
 impl PWMInner {
-    const fn new(base_addr: usize) -> PWMInner {
-        PWMInner { base_addr }
+    const fn new(phys_base_addr: usize) -> PWMInner {
+        PWMInner {
+            phys_base_addr,
+            mmio: None,
+            clock_hz: 0,
+            streams: [None; 2],
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> core::result::Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
     }
 
     fn ptr(&self) -> *const RegisterBlock {
-        self.base_addr as *const _
+        self.mmio
+            .as_ref()
+            .expect("PWM registers are not mapped")
+            .ptr()
+    }
+
+    fn set_enabled(&self, channel: Channel, enabled: bool) {
+        match (channel, enabled) {
+            (Channel::Ch1, true) => self.CTL.modify(CTL::PWEN1::Enabled),
+            (Channel::Ch1, false) => self.CTL.modify(CTL::PWEN1::Disabled),
+            (Channel::Ch2, true) => self.CTL.modify(CTL::PWEN2::Enabled),
+            (Channel::Ch2, false) => self.CTL.modify(CTL::PWEN2::Disabled),
+        }
+    }
+
+    fn set_mode(&self, channel: Channel, mode: Mode) {
+        match (channel, mode) {
+            (Channel::Ch1, Mode::MarkSpace) => self.CTL.modify(CTL::MSEN1::MSTransmission),
+            (Channel::Ch1, Mode::Balanced) => self.CTL.modify(CTL::MSEN1::PWMAlgorithm),
+            (Channel::Ch2, Mode::MarkSpace) => self.CTL.modify(CTL::MSEN2::MSTransmission),
+            (Channel::Ch2, Mode::Balanced) => self.CTL.modify(CTL::MSEN2::PWMAlgorithm),
+        }
+    }
+
+    /// Inverting also inverts the silence bit, so that a stopped channel rests at the inactive
+    /// level.
+    fn set_polarity(&self, channel: Channel, polarity: Polarity) {
+        match (channel, polarity) {
+            (Channel::Ch1, Polarity::Normal) => {
+                self.CTL.modify(CTL::POLA1::LowHigh + CTL::SBIT1::CLEAR)
+            }
+            (Channel::Ch1, Polarity::Inverted) => {
+                self.CTL.modify(CTL::POLA1::HighLow + CTL::SBIT1::SET)
+            }
+            (Channel::Ch2, Polarity::Normal) => {
+                self.CTL.modify(CTL::POLA2::LowHigh + CTL::SBIT2::CLEAR)
+            }
+            (Channel::Ch2, Polarity::Inverted) => {
+                self.CTL.modify(CTL::POLA2::HighLow + CTL::SBIT2::SET)
+            }
+        }
+    }
+
+    fn range_reg(&self, channel: Channel) -> &ReadWrite<u32> {
+        match channel {
+            Channel::Ch1 => &self.RNG1,
+            Channel::Ch2 => &self.RNG2,
+        }
+    }
+
+    fn data_reg(&self, channel: Channel) -> &ReadWrite<u32> {
+        match channel {
+            Channel::Ch1 => &self.DAT1,
+            Channel::Ch2 => &self.DAT2,
+        }
+    }
+
+    /// Set the data register to `duty` of the range.
+    fn set_duty_cycle(&self, channel: Channel, duty: f32) -> Result<()> {
+        if !(0.0..=1.0).contains(&duty) {
+            return Err(Error::InvalidDutyCycle);
+        }
+
+        let range = self.range_reg(channel).get();
+        self.data_reg(channel)
+            .set((range as f32 * duty + 0.5) as u32);
+
+        Ok(())
+    }
+
+    /// Feed `channel` from the FIFO as `stream` says, or from its data register with `None`.
+    fn set_source(&mut self, channel: Channel, stream: Option<(StreamMode, Underrun)>) {
+        let (fifo, serialiser, repeat) = match stream {
+            Some((mode, underrun)) => (
+                true,
+                mode == StreamMode::Serialiser,
+                underrun == Underrun::RepeatLast,
+            ),
+            None => (false, false, false),
+        };
+
+        match channel {
+            Channel::Ch1 => self.CTL.modify(
+                CTL::USEF1.val(fifo as u32)
+                    + CTL::MODE1.val(serialiser as u32)
+                    + CTL::RPTL1.val(repeat as u32),
+            ),
+            Channel::Ch2 => self.CTL.modify(
+                CTL::USEF2.val(fifo as u32)
+                    + CTL::MODE2.val(serialiser as u32)
+                    + CTL::RPTL2.val(repeat as u32),
+            ),
+        }
+        self.streams[channel as usize] = stream.map(|(_, underrun)| underrun);
+    }
+
+    /// Whether an enabled channel takes words from the FIFO.
+    fn is_streaming(&self) -> bool {
+        let ctl = self.CTL.extract();
+
+        (self.streams[0].is_some() && ctl.is_set(CTL::PWEN1))
+            || (self.streams[1].is_some() && ctl.is_set(CTL::PWEN2))
+    }
+
+    /// Clear the errors in the status register, returning the first. Running empty is only an
+    /// error for the channels that stream with `Underrun::Silence`.
+    fn take_error(&self) -> Result<()> {
+        let sta = self.STA.extract();
+        self.STA.write(
+            STA::BERR::SET + STA::GAPO1::SET + STA::GAPO2::SET + STA::RERR1::SET + STA::WERR1::SET,
+        );
+        let silent = |channel: Channel| self.streams[channel as usize] == Some(Underrun::Silence);
+
+        if sta.is_set(STA::BERR) {
+            Err(Error::BusError)
+        } else if sta.is_set(STA::WERR1) {
+            Err(Error::FifoOverrun)
+        } else if sta.is_set(STA::GAPO1) && silent(Channel::Ch1) {
+            Err(Error::Gap(Channel::Ch1))
+        } else if sta.is_set(STA::GAPO2) && silent(Channel::Ch2) {
+            Err(Error::Gap(Channel::Ch2))
+        } else if sta.is_set(STA::RERR1) && (silent(Channel::Ch1) || silent(Channel::Ch2)) {
+            Err(Error::FifoUnderrun)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The bus address of the FIFO register, for DMA engines.
+    fn fifo_bus_addr(&self) -> usize {
+        let offset = &self.FIF1 as *const _ as usize - self.ptr() as usize;
+
+        self.mmio
+            .as_ref()
+            .map_or(0, |mmio| mmio.bus_addr() + offset)
+    }
+
+    /// How long the streaming channels take for `words` at most, one period of the slower channel
+    /// each.
+    fn stream_time(&self, words: usize) -> Result<Duration> {
+        if self.clock_hz == 0 {
+            return Err(Error::NoClock);
+        }
+        let range = u64::from(cmp::max(self.RNG1.get(), self.RNG2.get()));
+
+        Ok(Duration::from_micros(
+            words as u64 * range * 1_000_000 / u64::from(self.clock_hz),
+        ))
     }
 }
 
 pub struct PWM {
+    phys_base_addr: usize,
     inner: Mutex<PWMInner>,
-    clock: Clock,
-    gpio_to_pwm_port: [u32; 28],
+    clock: Option<&'static Clock>,
+    dma: Option<&'static dyn interface::dma::Engine>,
 }
 
 impl PWM {
-    pub const unsafe fn new(base_addr: usize, clock_base_addr: usize) -> PWM {
+    /// Create an instance for the registers at the physical address `phys_base_addr`.
+    pub const unsafe fn new(phys_base_addr: usize) -> PWM {
         PWM {
-            inner: Mutex::new(PWMInner::new(base_addr)),
-            clock: Clock::new(clock_base_addr),
-            gpio_to_pwm_port: [
-                0, 0, 0, 0, 0, 0, 0, 0, //  0 ->  7
-                0, 0, 0, 0, 1, 2, 0, 0, //  8 -> 15
-                0, 0, 1, 2, 0, 0, 0, 0, // 16 -> 23
-                0, 0, 0, 0, // 24 -> 28
-            ],
+            phys_base_addr,
+            inner: Mutex::new(PWMInner::new(phys_base_addr)),
+            clock: None,
+            dma: None,
         }
     }
-}
 
-impl interface::pwm::Set for PWM {
-    fn set_mode(&self, mode: u32) {
-        let inner = &self.inner.lock();
-        if mode == 0 {
-            inner.CTL.modify(
-                CTL::PWEN1::Enabled
-                    + CTL::PWEN2::Enabled
-                    + CTL::MSEN1::MSTransmission
-                    + CTL::MSEN2::MSTransmission,
+    /// Drive the PWM from `clock`. Must be called before `init()`.
+    pub fn set_clock_manager(&mut self, clock: &'static Clock) {
+        self.clock = Some(clock);
+    }
+
+    /// Feed the FIFO through `dma` when streaming long runs of samples.
+    pub fn set_dma(&mut self, dma: &'static dyn interface::dma::Engine) {
+        self.dma = Some(dma);
+    }
+
+    /// Let `dma` move `samples` into the FIFO, by way of the coherent `buffer`.
+    fn write_all_samples_dma(
+        &self,
+        dma: &dyn interface::dma::Engine,
+        buffer: &DMABuffer,
+        samples: &[u32],
+    ) -> Result<()> {
+        let ptr = buffer.as_mut_ptr() as *mut u32;
+        for (i, &sample) in samples.iter().enumerate() {
+            unsafe { ptr::write_volatile(ptr.add(i), sample) };
+        }
+
+        let (fifo, timeout) = {
+            let inner = self.inner.lock();
+            inner.take_error()?;
+            if !inner.is_streaming() {
+                return Err(Error::NotStreaming);
+            }
+
+            inner.DMAC.write(
+                DMAC::ENAB::Enabled + DMAC::PANIC.val(FIFO_LEN - 1) + DMAC::DREQ.val(FIFO_LEN - 1),
             );
-        } else {
-            inner.CTL.modify(CTL::PWEN1::Enabled + CTL::PWEN2::Enabled);
+            let time = inner.stream_time(samples.len())?;
+            (inner.fifo_bus_addr(), time * 2 + Duration::from_millis(10))
+        };
+
+        let segment = Segment {
+            src: Endpoint::Memory(buffer.bus_addr()),
+            dest: Endpoint::Peripheral(fifo, DREQ),
+            len: buffer.size(),
+        };
+        let result = unsafe { dma.transfer(&[segment], timeout) };
+
+        let inner = self.inner.lock();
+        inner.DMAC.modify(DMAC::ENAB::Disabled);
+        result.map_err(Error::Dma)?;
+        inner.take_error()
+    }
+
+    /// The channel `pin` outputs, owning the pin.
+    pub fn channel(&'static self, pin: Pin<mode::Pwm>) -> PwmChannel {
+        PwmChannel { pwm: self, pin }
+    }
+}
+
+/// One of the two PWM channels, with the pin it is routed to.
+pub struct PwmChannel {
+    pwm: &'static PWM,
+    pin: Pin<mode::Pwm>,
+}
+
+#[allow(dead_code)]
+impl PwmChannel {
+    pub fn channel(&self) -> Channel {
+        self.pin.channel()
+    }
+
+    /// Give up the channel, returning its pin.
+    pub fn release(self) -> Pin<mode::Pwm> {
+        self.pin
+    }
+}
+
+/// The duty is the number of high cycles per period, the range.
+impl embedded_hal::PwmPin for PwmChannel {
+    type Duty = u32;
+
+    fn disable(&mut self) {
+        self.pwm.inner.lock().set_enabled(self.channel(), false);
+    }
+
+    fn enable(&mut self) {
+        self.pwm.inner.lock().set_enabled(self.channel(), true);
+    }
+
+    fn get_duty(&self) -> u32 {
+        self.pwm.inner.lock().data_reg(self.channel()).get()
+    }
+
+    fn get_max_duty(&self) -> u32 {
+        self.pwm.inner.lock().range_reg(self.channel()).get()
+    }
+
+    fn set_duty(&mut self, duty: u32) {
+        self.pwm.inner.lock().data_reg(self.channel()).set(duty);
+    }
+}
+
+/// Channels start disabled, in mark-space mode with normal polarity.
+impl interface::pwm::Control for PWM {
+    fn configure(&self, channel: Channel, frequency_hz: u32, duty: f32) -> Result<()> {
+        let inner = self.inner.lock();
+        if frequency_hz == 0 || frequency_hz > inner.clock_hz {
+            return Err(Error::InvalidFrequency(frequency_hz));
+        }
+
+        let range = (inner.clock_hz + frequency_hz / 2) / frequency_hz;
+        inner.range_reg(channel).set(range);
+        inner.set_duty_cycle(channel, duty)
+    }
+
+    fn set_duty_cycle(&self, channel: Channel, duty: f32) -> Result<()> {
+        self.inner.lock().set_duty_cycle(channel, duty)
+    }
+
+    fn frequency(&self, channel: Channel) -> u32 {
+        let inner = self.inner.lock();
+
+        match inner.range_reg(channel).get() {
+            0 => 0,
+            range => inner.clock_hz / range,
         }
     }
 
-    fn set_range(&self, range: u32) {
-        let inner = &self.inner.lock();
-        inner.RNG1.set(range);
-        arch::timer().spin_for(Duration::from_secs_f32(0.01));
+    fn set_mode(&self, channel: Channel, mode: Mode) {
+        self.inner.lock().set_mode(channel, mode);
+    }
 
-        inner.RNG2.set(range);
-        arch::timer().spin_for(Duration::from_secs_f32(0.01));
+    fn set_polarity(&self, channel: Channel, polarity: Polarity) {
+        self.inner.lock().set_polarity(channel, polarity);
     }
 
-    fn set_clock(&self, divisor: u32) {
-        let inner = &self.inner.lock();
-        let pwm_control = inner.CTL.get() as u32;
-        inner.CTL.set(0);
+    fn enable(&self, channel: Channel) {
+        self.inner.lock().set_enabled(channel, true);
+    }
 
-        &self.clock.init(divisor & 0x0FFF);
-        inner.CTL.set(pwm_control);
+    fn disable(&self, channel: Channel) {
+        self.inner.lock().set_enabled(channel, false);
     }
 }
 
-impl interface::pwm::Output for PWM {
-    fn write(&self, pin: u32, value: u32) {
-        let inner = &self.inner.lock();
-        match &self.gpio_to_pwm_port[pin as usize] {
-            0 => arch::spin_for_cycles(1),
-            1 => inner.DAT1.set(value),
-            2 => inner.DAT2.set(value),
-            _ => arch::spin_for_cycles(1),
+/// Words are only written while the FIFO has room, so overruns are not expected.
+impl interface::pwm::Stream for PWM {
+    fn start_stream(&self, channel: Channel, mode: StreamMode, underrun: Underrun) {
+        let mut inner = self.inner.lock();
+
+        inner.set_source(channel, Some((mode, underrun)));
+        if mode == StreamMode::Serialiser {
+            inner.range_reg(channel).set(32);
+        }
+        inner.CTL.modify(CTL::CLRF1::ClearFIFO);
+        let _ = inner.take_error();
+    }
+
+    fn stop_stream(&self, channel: Channel) {
+        let mut inner = self.inner.lock();
+
+        inner.set_source(channel, None);
+        let _ = inner.take_error();
+    }
+
+    fn range(&self, channel: Channel) -> u32 {
+        self.inner.lock().range_reg(channel).get()
+    }
+
+    /// With a DMA engine, runs longer than the FIFO are copied into DMA-coherent memory and moved
+    /// by the engine, paced by the PWM. Without one, or without DMA memory, the CPU feeds the FIFO.
+    fn write_all_samples(&self, mut samples: &[u32]) -> Result<()> {
+        if let Some(dma) = self.dma.filter(|_| samples.len() > FIFO_LEN as usize) {
+            if let Some(buffer) = dma::alloc(samples.len() * 4, 4) {
+                return self.write_all_samples_dma(dma, &buffer, samples);
+            }
+        }
+
+        while !samples.is_empty() {
+            let queued = self.write_samples(samples)?;
+            samples = &samples[queued..];
+        }
+
+        Ok(())
+    }
+
+    fn write_samples(&self, samples: &[u32]) -> Result<usize> {
+        let inner = self.inner.lock();
+        inner.take_error()?;
+
+        let mut queued = 0;
+        for &sample in samples {
+            if inner.STA.is_set(STA::FULL1) {
+                break;
+            }
+            inner.FIF1.set(sample);
+            queued += 1;
+        }
+
+        // A full FIFO nobody takes words from never makes room.
+        if queued == 0 && !samples.is_empty() && !inner.is_streaming() {
+            return Err(Error::NotStreaming);
+        }
+
+        Ok(queued)
+    }
+
+    /// The lock is released between polls, so the other channel stays controllable.
+    fn drain(&self) -> Result<()> {
+        loop {
+            let inner = self.inner.lock();
+            inner.take_error()?;
+
+            if inner.STA.is_set(STA::EMPT1) {
+                return Ok(());
+            }
+            if !inner.is_streaming() {
+                return Err(Error::NotStreaming);
+            }
+        }
+    }
+}
+
+impl interface::driver::DeviceDriver for PWM {
+    fn compatible(&self) -> &str {
+        "brcm,bcm2835-pwm"
+    }
+
+    /// The BCM2711's second PWM shares the `compatible`, so the compiled-in address picks the
+    /// node.
+    fn fixed_phys_base_addr(&self) -> Option<usize> {
+        Some(self.phys_base_addr)
+    }
+
+    /// The clock manager feeds the PWM and the GPIO routes it to the pins.
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[clock::COMPATIBLE, gpio::COMPATIBLE]
+    }
+
+    fn set_phys_base_addr(&mut self, phys_base_addr: usize) {
+        self.phys_base_addr = phys_base_addr;
+        self.inner.get_mut().phys_base_addr = phys_base_addr;
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let clock = match self.clock {
+            Some(clock) => clock,
+            None => return Err(interface::driver::Error::Device("No clock manager")),
         };
+
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        inner
+            .CTL
+            .write(CTL::MSEN1::MSTransmission + CTL::MSEN2::MSTransmission);
+
+        let divisor = (clock.source_hz() + MAX_CLOCK_HZ - 1) / MAX_CLOCK_HZ;
+        let divisor = cmp::min(cmp::max(divisor, 1), clock::MAX_DIVISOR);
+        clock.start(divisor);
+        inner.clock_hz = clock.source_hz() / divisor;
+
+        Ok(())
     }
 }
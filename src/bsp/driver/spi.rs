@@ -0,0 +1,273 @@
+//! Driver for SPI0, the BCM2837's full SPI master.
+//!
+//! Transfers are polled. The TX FIFO is kept filled while the RX FIFO is drained, so the bus
+//! does not idle between bytes. The serial clock is divided from the VPU core clock.
+
+use crate::bsp::driver::gpio;
+use crate::{
+    arch,
+    arch::Mutex,
+    interface,
+    interface::{
+        spi::{ChipSelect, Error, Mode, Result},
+        time::Timer,
+    },
+    memory::{
+        mmio::{self, MMIOMapping},
+        MapError,
+    },
+};
+use core::{mem, ops, time::Duration};
+use register::{mmio::ReadWrite, register_bitfields, register_structs};
+
+register_bitfields! {
+    u32,
+
+    /// Control and Status
+    CS [
+        RXD OFFSET(17) NUMBITS(1) [],
+        TXD OFFSET(18) NUMBITS(1) [],
+        DONE OFFSET(16) NUMBITS(1) [],
+        TA OFFSET(7) NUMBITS(1) [],
+        CLEAR OFFSET(4) NUMBITS(2) [
+            Fifos = 0b11
+        ],
+        CPOL OFFSET(3) NUMBITS(1) [
+            IdleLow = 0,
+            IdleHigh = 1
+        ],
+        CPHA OFFSET(2) NUMBITS(1) [
+            Middle = 0,
+            Beginning = 1
+        ],
+        CS OFFSET(0) NUMBITS(2) [
+            Cs0 = 0b00,
+            Cs1 = 0b01
+        ]
+    ],
+
+    /// Clock Divider
+    CLK [
+        CDIV OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => FIFO: ReadWrite<u32>),
+        (0x08 => CLK: ReadWrite<u32, CLK::Register>),
+        (0x0C => _reserved1),
+        (0x18 => @END),
+    }
+}
+
+/// Safe for most devices.
+const DEFAULT_SPEED_HZ: u32 = 1_000_000;
+
+/// Depth of the TX and RX FIFOs in bytes.
+const FIFO_LEN: usize = 64;
+
+/// Settings of the device at one chip select.
+#[derive(Copy, Clone)]
+struct Device {
+    cdiv: u32,
+    mode: Mode,
+}
+
+struct SPIInner {
+    phys_base_addr: usize,
+    core_clock_hz: u32,
+    mmio: Option<MMIOMapping<RegisterBlock>>,
+    devices: [Device; 2],
+}
+
+impl ops::Deref for SPIInner {
+    type Target = RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr() }
+    }
+}
+
+impl SPIInner {
+    const fn new(phys_base_addr: usize, core_clock_hz: u32) -> SPIInner {
+        let device = Device {
+            cdiv: divider(core_clock_hz, DEFAULT_SPEED_HZ),
+            mode: Mode::Mode0,
+        };
+
+        SPIInner {
+            phys_base_addr,
+            core_clock_hz,
+            mmio: None,
+            devices: [device; 2],
+        }
+    }
+
+    /// Map the registers. Until then, every register access panics.
+    unsafe fn map_mmio(&mut self) -> core::result::Result<(), MapError> {
+        if self.mmio.is_none() {
+            let size = mem::size_of::<RegisterBlock>();
+            self.mmio = Some(mmio::ioremap(self.phys_base_addr, size)?);
+        }
+
+        Ok(())
+    }
+
+    fn ptr(&self) -> *const RegisterBlock {
+        self.mmio
+            .as_ref()
+            .expect("SPI registers are not mapped")
+            .ptr()
+    }
+
+    fn device_mut(&mut self, cs: ChipSelect) -> &mut Device {
+        &mut self.devices[cs as usize]
+    }
+
+    /// When a transfer of `len` bytes at `cdiv` should be done by.
+    fn deadline(&self, len: usize, cdiv: u32) -> Duration {
+        let speed_hz = u64::from(self.core_clock_hz / cdiv);
+        let transfer = Duration::from_micros(8 * len as u64 * 1_000_000 / speed_hz);
+
+        arch::timer().uptime() + transfer * 4 + Duration::from_millis(1)
+    }
+
+    /// Send `tx`, receiving into `rx` if there is one, which must be as long.
+    fn transfer(&self, cs: ChipSelect, tx: &[u8], mut rx: Option<&mut [u8]>) -> Result<()> {
+        let device = self.devices[cs as usize];
+        let deadline = self.deadline(tx.len(), device.cdiv);
+        let (cpol, cpha) = match device.mode {
+            Mode::Mode0 => (CS::CPOL::IdleLow, CS::CPHA::Middle),
+            Mode::Mode1 => (CS::CPOL::IdleLow, CS::CPHA::Beginning),
+            Mode::Mode2 => (CS::CPOL::IdleHigh, CS::CPHA::Middle),
+            Mode::Mode3 => (CS::CPOL::IdleHigh, CS::CPHA::Beginning),
+        };
+        let chip_select = match cs {
+            ChipSelect::Cs0 => CS::CS::Cs0,
+            ChipSelect::Cs1 => CS::CS::Cs1,
+        };
+
+        self.CLK.write(CLK::CDIV.val(device.cdiv));
+        self.CS.write(CS::CLEAR::Fifos + cpol + cpha + chip_select);
+        self.CS.modify(CS::TA::SET);
+
+        // DONE follows the last byte, after which only the chip select remains to be released.
+        let (mut sent, mut received) = (0, 0);
+        while received < tx.len() || !self.CS.is_set(CS::DONE) {
+            // Never more bytes in flight than the RX FIFO holds, so that none are dropped.
+            while sent < tx.len() && sent - received < FIFO_LEN && self.CS.is_set(CS::TXD) {
+                self.FIFO.set(tx[sent].into());
+                sent += 1;
+            }
+            while received < sent && self.CS.is_set(CS::RXD) {
+                let byte = self.FIFO.get() as u8;
+                if let Some(rx) = rx.as_mut() {
+                    rx[received] = byte;
+                }
+                received += 1;
+            }
+
+            if arch::timer().uptime() > deadline {
+                // Also clears TA, which releases the chip select.
+                self.CS.write(CS::CLEAR::Fifos);
+                return Err(Error::Timeout);
+            }
+        }
+        self.CS.modify(CS::TA::CLEAR);
+
+        Ok(())
+    }
+}
+
+/// The even clock divider for the fastest serial clock up to `speed_hz`. Callers make sure
+/// `speed_hz` is not 0.
+const fn divider(core_clock_hz: u32, speed_hz: u32) -> u32 {
+    let cdiv = (core_clock_hz as u64 + speed_hz as u64 - 1) / speed_hz as u64;
+
+    ((cdiv + 1) & !1) as u32
+}
+
+pub struct SPI {
+    phys_base_addr: usize,
+    inner: Mutex<SPIInner>,
+}
+
+impl SPI {
+    /// Create an instance for the registers at the physical address `phys_base_addr`, clocked by
+    /// a core clock of `core_clock_hz`.
+    pub const unsafe fn new(phys_base_addr: usize, core_clock_hz: u32) -> SPI {
+        SPI {
+            phys_base_addr,
+            inner: Mutex::new(SPIInner::new(phys_base_addr, core_clock_hz)),
+        }
+    }
+}
+
+/// Empty transfers return without selecting the device.
+impl interface::spi::Master for SPI {
+    fn set_speed(&self, cs: ChipSelect, speed_hz: u32) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let cdiv = match speed_hz {
+            0 => return Err(Error::InvalidSpeed(speed_hz)),
+            _ => divider(inner.core_clock_hz, speed_hz),
+        };
+        if cdiv > 0xFFFE {
+            return Err(Error::InvalidSpeed(speed_hz));
+        }
+
+        inner.device_mut(cs).cdiv = cdiv;
+        Ok(())
+    }
+
+    fn set_mode(&self, cs: ChipSelect, mode: Mode) {
+        self.inner.lock().device_mut(cs).mode = mode;
+    }
+
+    fn transfer(&self, cs: ChipSelect, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        if tx.len() != rx.len() {
+            return Err(Error::LengthMismatch(tx.len(), rx.len()));
+        }
+        if tx.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.lock().transfer(cs, tx, Some(rx))
+    }
+
+    fn write(&self, cs: ChipSelect, tx: &[u8]) -> Result<()> {
+        if tx.is_empty() {
+            return Ok(());
+        }
+
+        self.inner.lock().transfer(cs, tx, None)
+    }
+}
+
+impl interface::driver::DeviceDriver for SPI {
+    fn compatible(&self) -> &str {
+        "brcm,bcm2835-spi"
+    }
+
+    /// The BCM2711's additional SPI masters share the `compatible`, so the compiled-in address
+    /// picks the node.
+    fn fixed_phys_base_addr(&self) -> Option<usize> {
+        Some(self.phys_base_addr)
+    }
+
+    /// The GPIO routes the bus to the pins.
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[gpio::COMPATIBLE]
+    }
+
+    fn init(&self) -> interface::driver::Result {
+        let mut inner = self.inner.lock();
+        unsafe { inner.map_mmio() }?;
+
+        inner.CS.write(CS::CLEAR::Fifos);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+mod memory_map;
+mod virt_mem_layout;
+
+use super::{common, driver};
+use crate::arch::psci::{self, Conduit};
+use crate::driver::{driver_manager_mut, DeviceDriverDescriptor};
+use crate::memory::{KernelVirtualLayout, MapError};
+use crate::{arch, fdt, interface, warn};
+use core::{fmt, ops::RangeInclusive};
+
+/// Stack addresses are physical, because they are set up before the MMU is turned on.
+pub const BOOT_CORE_STACK_START: u64 = 0x4028_0000;
+
+// 4k stack for slave cores, reserved by the linker script after .bss.
+pub const SLAVE_STACK_SIZE: u64 = 4096;
+
+extern "C" {
+    static __stacks_start: usize;
+}
+
+/// The physical address the stack of slave core `core_id` grows down from.
+///
+/// Inlined, because the slave cores call it before they have a stack.
+#[inline(always)]
+pub fn slave_stack_start(core_id: u64) -> u64 {
+    let stacks_start = unsafe { &__stacks_start as *const usize as usize };
+
+    crate::memory::kernel_virt_to_phys(stacks_start) as u64 + core_id * SLAVE_STACK_SIZE
+}
+
+pub const KERNEL_VIRT_OFFSET: usize = memory_map::KERNEL_VIRT_OFFSET;
+
+/// Number of cores the kernel starts. It identifies cores by the lowest two MPIDR bits.
+const NUM_CORES: u64 = 4;
+
+static mut PL011_UART: driver::PL011Uart =
+    unsafe { driver::PL011Uart::new(memory_map::mmio::PL011_UART_BASE) };
+
+static mut GIC: driver::GICv2 =
+    unsafe { driver::GICv2::new(memory_map::mmio::GICD_BASE, memory_map::mmio::GICC_BASE) };
+
+pub fn board_name() -> &'static str {
+    "QEMU virt"
+}
+
+pub fn console() -> &'static mut impl interface::console::All {
+    unsafe { &mut PL011_UART }
+}
+
+/// Map the console for `panic_console_out()`, right after the page tables were populated from the
+/// virtual memory layout.
+pub unsafe fn map_panic_console_out() -> Result<(), MapError> {
+    PL011_UART.map_panic_uart()
+}
+
+pub unsafe fn panic_console_out() -> impl fmt::Write {
+    let uart = PL011_UART.panic_uart();
+    uart.init();
+    uart
+}
+
+#[allow(dead_code)]
+pub fn irq_manager() -> &'static impl interface::irq::Manager {
+    unsafe { &GIC }
+}
+
+/// Register the board's drivers with the driver manager.
+///
+/// # Safety
+///
+/// - Must be called once, before `probe_device_drivers()`.
+pub unsafe fn register_device_drivers() {
+    let manager = driver_manager_mut();
+
+    manager.register(DeviceDriverDescriptor::new(&mut GIC, None));
+    manager.register(DeviceDriverDescriptor::new(&mut PL011_UART, None));
+}
+
+/// Point the drivers at the devices of QEMU's device tree, matched by `compatible()`. Drivers
+/// without a matching node keep their compiled-in addresses.
+///
+/// # Safety
+///
+/// - Must be called before the drivers are initialized.
+pub unsafe fn probe_device_drivers() {
+    use interface::driver::DeviceDriver;
+
+    let tree = match fdt::device_tree() {
+        Ok(tree) => tree,
+        Err(_) => return,
+    };
+
+    common::probe_device_drivers(tree);
+
+    // The GIC's second `reg` entry is its CPU interface.
+    let gicc_addr = common::find_device(tree, GIC.compatible()).and_then(|gic| gic.reg().nth(1));
+    if let Some((addr, _)) = gicc_addr {
+        GIC.set_cpu_interface_phys_base_addr(addr as usize);
+    }
+}
+
+/// The PSCI conduit from the device tree. QEMU uses `smc` when it emulates EL2, which the kernel
+/// requires.
+fn psci_conduit() -> Conduit {
+    let method = fdt::device_tree()
+        .ok()
+        .and_then(|tree| tree.find_node("/psci"))
+        .and_then(|psci| psci.property("method"))
+        .and_then(|property| property.as_str());
+
+    match method {
+        Some("hvc") => Conduit::Hvc,
+        _ => Conduit::Smc,
+    }
+}
+
+/// Power on the slave cores with PSCI CPU_ON. QEMU has no spin tables.
+pub unsafe fn activate_other_cores() {
+    let conduit = psci_conduit();
+    let entry = arch::phys_entry_point();
+
+    for core_id in 1..NUM_CORES {
+        match psci::cpu_on(conduit, core_id, entry, 0) {
+            // QEMU was started with fewer cores.
+            Ok(()) | Err(psci::PsciError::InvalidParameters) => (),
+            Err(err) => warn!("Core {}: PSCI CPU_ON failed: {}", core_id, err),
+        }
+    }
+}
+
+/// QEMU does not pass the device tree in x0 to bare-metal images, but puts it at the start of RAM.
+pub const fn default_dtb_phys_addr() -> Option<usize> {
+    Some(memory_map::phys::DTB)
+}
+
+/// Return a reference to the virtual memory layout.
+pub fn virt_mem_layout() -> &'static KernelVirtualLayout<{ virt_mem_layout::NUM_MEM_RANGES }> {
+    &virt_mem_layout::LAYOUT
+}
+
+/// Describe the physical memory from QEMU's device tree, or from compiled-in defaults if it does
+/// not list any RAM.
+pub fn init_phys_memory_map() {
+    common::init_phys_memory_map(
+        memory_map::phys::RAM_BASE..=memory_map::phys::DEFAULT_RAM_END_INCLUSIVE,
+        dma_range(),
+        memory_map::heap::START,
+    );
+}
+
+/// Return the memory range of the kernel heap, up to the end of the free RAM it lies in.
+pub fn kernel_heap_range() -> RangeInclusive<usize> {
+    common::kernel_heap_range(memory_map::heap::START, memory_map::phys::END_INCLUSIVE)
+}
+
+/// Return the memory range reserved for DMA-coherent buffers.
+pub fn dma_range() -> RangeInclusive<usize> {
+    RangeInclusive::new(memory_map::dma::START, memory_map::dma::END_INCLUSIVE)
+}
+
+/// Devices see RAM at its physical address.
+pub const fn phys_to_bus(phys_addr: usize) -> usize {
+    phys_addr
+}
+
+/// Devices see each other at their physical addresses as well.
+pub const fn mmio_phys_to_bus(phys_addr: usize) -> usize {
+    phys_addr
+}
+
+/// Return the virtual area in which device windows are mapped on demand.
+pub fn ioremap_range() -> RangeInclusive<usize> {
+    RangeInclusive::new(
+        memory_map::ioremap::START,
+        memory_map::ioremap::END_INCLUSIVE,
+    )
+}
+
+/// Return the physical device MMIO window, which lies below RAM.
+pub const fn phys_mmio_range() -> RangeInclusive<usize> {
+    RangeInclusive::new(
+        memory_map::phys::MMIO_BASE,
+        memory_map::phys::MMIO_END_INCLUSIVE,
+    )
+}
+
+/// Return the address space size in bytes.
+pub const fn addr_space_size() -> usize {
+    memory_map::phys::END_INCLUSIVE + 1
+}
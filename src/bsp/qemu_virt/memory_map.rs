@@ -0,0 +1,57 @@
+/// The kernel runs in the upper half of the address space. Physical address `x` is mapped at
+/// `KERNEL_VIRT_OFFSET + x`, which is the first address translated through TTBR1 with a 4 GiB
+/// upper half.
+pub const KERNEL_VIRT_OFFSET: usize = 0xFFFF_FFFF_0000_0000;
+
+/// Physical address space boundaries. The virt machine places its devices in the first GiB and RAM
+/// above it.
+#[rustfmt::skip]
+pub mod phys {
+    pub const MMIO_BASE:       usize =        0x0000_0000;
+    pub const MMIO_END_INCLUSIVE: usize =     0x3FFF_FFFF;
+    pub const RAM_BASE:        usize =        0x4000_0000;
+    pub const END_INCLUSIVE:   usize =        0xFFFF_FFFF;
+
+    /// QEMU's default of 128 MiB. Only used if there is no device tree.
+    pub const DEFAULT_RAM_END_INCLUSIVE: usize = 0x47FF_FFFF;
+
+    /// QEMU places the device tree at the start of RAM for bare-metal images.
+    pub const DTB:             usize =        RAM_BASE;
+}
+
+/// Buffers shared with DMA capable devices, mapped non-cacheable. Placed well above the kernel
+/// image, aligned to the 64 KiB page size.
+#[rustfmt::skip]
+pub mod dma {
+    use super::{phys, KERNEL_VIRT_OFFSET};
+
+    pub const START:           usize = KERNEL_VIRT_OFFSET + phys::RAM_BASE + 0x0100_0000;
+    pub const END_INCLUSIVE:   usize = KERNEL_VIRT_OFFSET + phys::RAM_BASE + 0x011F_FFFF;
+}
+
+/// The kernel heap starts directly after the DMA region and extends over the rest of the free RAM
+/// it lies in, see `kernel_heap_range()`.
+#[rustfmt::skip]
+pub mod heap {
+    use super::{phys, KERNEL_VIRT_OFFSET};
+
+    pub const START:           usize = KERNEL_VIRT_OFFSET + phys::RAM_BASE + 0x0120_0000;
+}
+
+/// Virtual area for device windows mapped on demand by `memory::mmio::ioremap()`. It reuses the
+/// upper half alias of the device area, which only the boot mapping maps linearly.
+#[rustfmt::skip]
+pub mod ioremap {
+    use super::{phys, KERNEL_VIRT_OFFSET};
+
+    pub const START:           usize = KERNEL_VIRT_OFFSET + 0x0800_0000;
+    pub const END_INCLUSIVE:   usize = KERNEL_VIRT_OFFSET + phys::MMIO_END_INCLUSIVE;
+}
+
+/// Physical addresses of the devices. Drivers map them with `memory::mmio::ioremap()`.
+#[rustfmt::skip]
+pub mod mmio {
+    pub const GICD_BASE:       usize = 0x0800_0000;
+    pub const GICC_BASE:       usize = 0x0801_0000;
+    pub const PL011_UART_BASE: usize = 0x0900_0000;
+}
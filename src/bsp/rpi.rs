@@ -1,10 +1,18 @@
 mod memory_map;
 mod virt_mem_layout;
 
-use super::driver;
-use crate::interface;
-use crate::memory::KernelVirtualLayout;
-use core::fmt;
+use super::{
+    common,
+    driver::{
+        self,
+        gpio::{mode, Pin},
+    },
+};
+use crate::driver::{driver_manager_mut, DeviceDriverDescriptor};
+use crate::memory::{KernelVirtualLayout, MapError};
+use crate::{arch, fdt, interface};
+use core::{fmt, ops::RangeInclusive};
+use embedded_hal::{blocking, serial, PwmPin};
 
 #[allow(dead_code)]
 pub const CORE_0_ID: u64 = 0;
@@ -23,17 +31,27 @@ pub const CORE_3_ID: u64 = 3;
 pub const MASTER_CORE_WAKEUP_ADDR: u64 = 0xd8;
 pub const SLAVE_CORES_WAKEUP_ADDR: [u64; 3] = [0xe0, 0xe8, 0xf0];
 
+/// Stack addresses are physical, because they are set up before the MMU is turned on.
 pub const BOOT_CORE_STACK_START: u64 = 0x80_000;
 
-// 4k stack for slave cores
-pub const SLAVE_STACK_SHIFT: u64 = 12;
-pub const SLAVE_STACK_PREAMBLE: u64 = 0b1001_0000;
-#[allow(dead_code)]
-pub const CORE_1_STACK_START: u64 = 0x094_000; // 0b1001_0001_0000_...
-#[allow(dead_code)]
-pub const CORE_2_STACK_START: u64 = 0x098_000; // 0b1001_0010_0000_...
-#[allow(dead_code)]
-pub const CORE_3_STACK_START: u64 = 0x09B_000; // 0b1001_0011_0000_...
+// 4k stack for slave cores, reserved by the linker script after .bss.
+pub const SLAVE_STACK_SIZE: u64 = 4096;
+
+extern "C" {
+    static __stacks_start: usize;
+}
+
+/// The physical address the stack of slave core `core_id` grows down from.
+///
+/// Inlined, because the slave cores call it before they have a stack.
+#[inline(always)]
+pub fn slave_stack_start(core_id: u64) -> u64 {
+    let stacks_start = unsafe { &__stacks_start as *const usize as usize };
+
+    crate::memory::kernel_virt_to_phys(stacks_start) as u64 + core_id * SLAVE_STACK_SIZE
+}
+
+pub const KERNEL_VIRT_OFFSET: usize = memory_map::KERNEL_VIRT_OFFSET;
 
 /*
 /// The address on which the RPi3 firmware loads every binary by default.
@@ -42,50 +60,333 @@ pub const BOARD_DEFAULT_LOAD_ADDRESS: usize = 0x80_000;
 
 static mut GPIO: driver::GPIO = unsafe { driver::GPIO::new(memory_map::mmio::GPIO_BASE) };
 
+/// GPIO 14 and 15, claimed for the console.
+static mut CONSOLE_PINS: Option<[Pin<mode::Alt>; 2]> = None;
+
+/// GPIO 2 and 3, claimed for the I2C bus.
+static mut I2C_PINS: Option<[Pin<mode::Alt>; 2]> = None;
+
+/// GPIO 7 to 11, claimed for the SPI bus.
+static mut SPI_PINS: Option<[Pin<mode::Alt>; 5]> = None;
+
+/// The two PWM channels, on GPIO 12 and 13.
+static mut PWM_CHANNELS: Option<[driver::PwmChannel; 2]> = None;
+
 static mut PL011_UART: driver::PL011Uart =
     unsafe { driver::PL011Uart::new(memory_map::mmio::PL011_UART_BASE) };
 
-static mut PWM: driver::PWM =
-    unsafe { driver::PWM::new(memory_map::mmio::PWM_BASE, memory_map::mmio::CLOCK_BASE) };
+/// The VPU core clock, which clocks the mini UART, the BSC and the SPI, as the firmware fixes it
+/// with `enable_uart=1`.
+#[cfg(feature = "bsp_rpi3")]
+const CORE_CLOCK_HZ: u32 = 250_000_000;
+#[cfg(feature = "bsp_rpi4")]
+const CORE_CLOCK_HZ: u32 = 500_000_000;
 
+static mut MINI_UART: driver::MiniUart =
+    unsafe { driver::MiniUart::new(memory_map::mmio::AUX_BASE, CORE_CLOCK_HZ) };
+
+static mut MAILBOX: driver::Mailbox =
+    unsafe { driver::Mailbox::new(memory_map::mmio::MAILBOX_BASE) };
+
+static mut CLOCK: driver::Clock = unsafe { driver::Clock::new(memory_map::mmio::CLOCK_BASE) };
+
+static mut PWM: driver::PWM = unsafe { driver::PWM::new(memory_map::mmio::PWM_BASE) };
+
+/// The DMA channels the firmware leaves to the ARM. On a Raspberry Pi 4, channels 11 to 14 are
+/// DMA4 channels, which the driver does not support.
+#[cfg(feature = "bsp_rpi3")]
+const DMA_CHANNEL_MASK: u16 = 0x7F35;
+#[cfg(feature = "bsp_rpi4")]
+const DMA_CHANNEL_MASK: u16 = 0x07F5;
+
+static mut DMA: driver::DMA =
+    unsafe { driver::DMA::new(memory_map::mmio::DMA_BASE, DMA_CHANNEL_MASK) };
+
+static mut I2C: driver::BSC =
+    unsafe { driver::BSC::new(memory_map::mmio::BSC1_BASE, CORE_CLOCK_HZ) };
+
+static mut SPI: driver::SPI =
+    unsafe { driver::SPI::new(memory_map::mmio::SPI0_BASE, CORE_CLOCK_HZ) };
+
+#[cfg(feature = "bsp_rpi4")]
+static mut GIC: driver::GICv2 =
+    unsafe { driver::GICv2::new(memory_map::mmio::GICD_BASE, memory_map::mmio::GICC_BASE) };
+
+/// The board's `model` from the device tree, if there is one.
 pub fn board_name() -> &'static str {
-    "Raspberry Pi 3"
+    let default = if cfg!(feature = "bsp_rpi4") {
+        "Raspberry Pi 4"
+    } else {
+        "Raspberry Pi 3"
+    };
+
+    fdt::device_tree()
+        .ok()
+        .and_then(|tree| tree.root().property("model"))
+        .and_then(|property| property.as_str())
+        .unwrap_or(default)
 }
 
+/// The primary console on GPIO 14 and 15: the PL011, or the mini UART with the
+/// `console_mini_uart` feature.
+#[cfg(not(feature = "console_mini_uart"))]
 pub fn console() -> &'static mut impl interface::console::All {
     unsafe { &mut PL011_UART }
 }
 
+#[cfg(feature = "console_mini_uart")]
+pub fn console() -> &'static mut impl interface::console::All {
+    unsafe { &mut MINI_UART }
+}
+
+/// The traits of the data UART: the console's, and `embedded-hal`'s serial traits for raw bytes.
+pub trait DataUart =
+    interface::console::All + serial::Read<u8> + serial::Write<u8> + blocking::serial::Write<u8>;
+
+/// The UART that is not the console, e.g. for a data link. Its pins are left as the firmware
+/// set them up: on a Raspberry Pi 3, the PL011 then talks to the Bluetooth chip.
+#[cfg(not(feature = "console_mini_uart"))]
+#[allow(dead_code)]
+pub fn data_uart() -> &'static mut impl DataUart {
+    unsafe { &mut MINI_UART }
+}
+
+#[cfg(feature = "console_mini_uart")]
+#[allow(dead_code)]
+pub fn data_uart() -> &'static mut impl DataUart {
+    unsafe { &mut PL011_UART }
+}
+
 pub fn gpio() -> &'static mut impl interface::gpio::All {
     unsafe { &mut GPIO }
 }
 
-pub fn pwm() -> &'static mut impl interface::pwm::All {
-    unsafe { &mut PWM }
+/// Take ownership of GPIO `pin`, as an input. Pins the BSP routed to a peripheral stay taken.
+pub fn take_gpio_pin(pin: u32) -> interface::gpio::Result<Pin<mode::Input>> {
+    unsafe { GPIO.take(pin) }
+}
+
+pub fn pwm() -> &'static (impl interface::pwm::Control + interface::pwm::Stream) {
+    unsafe { &PWM }
+}
+
+/// A PWM channel, on GPIO 12 or 13. `None` if the PWM failed to initialize.
+#[allow(dead_code)]
+pub fn pwm_channel(
+    channel: interface::pwm::Channel,
+) -> Option<&'static mut impl PwmPin<Duty = u32>> {
+    unsafe { PWM_CHANNELS.as_mut() }.map(|channels| &mut channels[channel as usize])
+}
+
+/// The I2C bus on GPIO 2 and 3, BSC1.
+pub fn i2c() -> &'static impl interface::i2c::Master {
+    unsafe { &I2C }
+}
+
+/// The SPI bus on GPIO 7 to 11, SPI0.
+#[allow(dead_code)]
+pub fn spi() -> &'static impl interface::spi::Master {
+    unsafe { &SPI }
+}
+
+/// The DMA controller, for transfers of DMA-coherent buffers.
+pub fn dma() -> &'static driver::DMA {
+    unsafe { &DMA }
 }
 
+/// The VideoCore firmware's property interface.
+pub fn mailbox() -> &'static driver::Mailbox {
+    unsafe { &MAILBOX }
+}
+
+/// Map the console for `panic_console_out()`, right after the page tables were populated from the
+/// virtual memory layout.
+#[cfg(not(feature = "console_mini_uart"))]
+pub unsafe fn map_panic_console_out() -> Result<(), MapError> {
+    PL011_UART.map_panic_uart()
+}
+
+#[cfg(feature = "console_mini_uart")]
+pub unsafe fn map_panic_console_out() -> Result<(), MapError> {
+    MINI_UART.map_panic_uart()
+}
+
+#[cfg(not(feature = "console_mini_uart"))]
+pub unsafe fn panic_console_out() -> impl fmt::Write {
+    let uart = PL011_UART.panic_uart();
+    uart.init();
+    uart
+}
+
+#[cfg(feature = "console_mini_uart")]
 pub unsafe fn panic_console_out() -> impl fmt::Write {
-    let uart = driver::PanicUart::new(memory_map::mmio::PL011_UART_BASE);
+    let uart = MINI_UART.panic_uart();
     uart.init();
     uart
 }
 
-pub fn device_drivers() -> [&'static mut dyn interface::driver::DeviceDriver; 2] {
-    unsafe { [&mut GPIO, &mut PL011_UART] }
+#[cfg(feature = "bsp_rpi4")]
+#[allow(dead_code)]
+pub fn irq_manager() -> &'static impl interface::irq::Manager {
+    unsafe { &GIC }
+}
+
+/// Claim GPIO 14 and 15 and route the console's TX and RX to them.
+unsafe fn gpio_post_init() -> interface::driver::Result {
+    let pins = if cfg!(feature = "console_mini_uart") {
+        GPIO.map_mini_uart()
+    } else {
+        GPIO.map_pl011_uart()
+    }?;
+    CONSOLE_PINS = Some(pins);
+
+    Ok(())
+}
+
+/// Claim GPIO 2 and 3 for the I2C bus.
+unsafe fn i2c_post_init() -> interface::driver::Result {
+    I2C_PINS = Some(GPIO.map_i2c1()?);
+
+    Ok(())
+}
+
+/// Claim GPIO 7 to 11 for the SPI bus.
+unsafe fn spi_post_init() -> interface::driver::Result {
+    SPI_PINS = Some(GPIO.map_spi0()?);
+
+    Ok(())
+}
+
+/// Claim GPIO 12 and 13 for the PWM channels.
+unsafe fn pwm_post_init() -> interface::driver::Result {
+    let channel_1 = PWM.channel(GPIO.take(12)?.into_pwm()?);
+    let channel_2 = PWM.channel(GPIO.take(13)?.into_pwm()?);
+    PWM_CHANNELS = Some([channel_1, channel_2]);
+
+    Ok(())
+}
+
+/// Register the board's drivers with the driver manager.
+///
+/// # Safety
+///
+/// - Must be called once, before `probe_device_drivers()`.
+pub unsafe fn register_device_drivers() {
+    let manager = driver_manager_mut();
+
+    PWM.set_clock_manager(&CLOCK);
+    PWM.set_dma(&DMA);
+    PL011_UART.set_dma(&DMA);
+
+    #[cfg(feature = "bsp_rpi4")]
+    manager.register(DeviceDriverDescriptor::new(&mut GIC, None));
+    manager.register(DeviceDriverDescriptor::new(&mut GPIO, Some(gpio_post_init)));
+    manager.register(DeviceDriverDescriptor::new(&mut PL011_UART, None));
+    manager.register(DeviceDriverDescriptor::new(&mut MINI_UART, None));
+    manager.register(DeviceDriverDescriptor::new(&mut MAILBOX, None));
+    manager.register(DeviceDriverDescriptor::new(&mut CLOCK, None));
+    manager.register(DeviceDriverDescriptor::new(&mut DMA, None));
+    manager.register(DeviceDriverDescriptor::new(&mut PWM, Some(pwm_post_init)));
+    manager.register(DeviceDriverDescriptor::new(&mut I2C, Some(i2c_post_init)));
+    manager.register(DeviceDriverDescriptor::new(&mut SPI, Some(spi_post_init)));
 }
 
-pub fn post_driver_init() {
-    unsafe {
-        GPIO.map_pl011_uart();
+/// Point the drivers at the devices of the firmware's device tree, matched by `compatible()`.
+/// Drivers without a matching node keep their compiled-in addresses.
+///
+/// # Safety
+///
+/// - Must be called before the drivers are initialized.
+pub unsafe fn probe_device_drivers() {
+    use interface::driver::DeviceDriver;
+
+    let tree = match fdt::device_tree() {
+        Ok(tree) => tree,
+        Err(_) => return,
+    };
+
+    common::probe_device_drivers(tree);
+
+    // The device tree can only take DMA channels away: the compiled-in mask also leaves out the
+    // channels of other layouts.
+    let dma_channel_mask = common::find_device(tree, DMA.compatible())
+        .and_then(|dma| dma.property("brcm,dma-channel-mask"))
+        .and_then(|property| property.as_u32());
+    if let Some(mask) = dma_channel_mask {
+        DMA.set_channel_mask(mask as u16 & DMA_CHANNEL_MASK);
+    }
+
+    // The GIC's second `reg` entry is its CPU interface.
+    #[cfg(feature = "bsp_rpi4")]
+    {
+        let gicc_addr =
+            common::find_device(tree, GIC.compatible()).and_then(|gic| gic.reg().nth(1));
+        if let Some((addr, _)) = gicc_addr {
+            GIC.set_cpu_interface_phys_base_addr(addr as usize);
+        }
     }
 }
 
+/// Wake the slave cores through the firmware's spin tables.
+pub unsafe fn activate_other_cores() {
+    arch::activate_other_cores(&SLAVE_CORES_WAKEUP_ADDR);
+}
+
+/// The firmware passes the device tree in x0, so there is no fallback address.
+pub const fn default_dtb_phys_addr() -> Option<usize> {
+    None
+}
+
 /// Return a reference to the virtual memory layout.
 pub fn virt_mem_layout() -> &'static KernelVirtualLayout<{ virt_mem_layout::NUM_MEM_RANGES }> {
     &virt_mem_layout::LAYOUT
 }
 
+/// Describe the physical memory from the firmware's device tree, or from compiled-in defaults if
+/// it does not list any RAM.
+pub fn init_phys_memory_map() {
+    common::init_phys_memory_map(
+        0..=memory_map::phys::DEFAULT_RAM_END_INCLUSIVE,
+        dma_range(),
+        memory_map::heap::START,
+    );
+}
+
+/// Return the memory range of the kernel heap, up to the end of the free RAM it lies in and below
+/// the MMIO window.
+pub fn kernel_heap_range() -> RangeInclusive<usize> {
+    common::kernel_heap_range(memory_map::heap::START, memory_map::phys::MMIO_BASE - 1)
+}
+
+/// Return the memory range reserved for DMA-coherent buffers.
+pub fn dma_range() -> RangeInclusive<usize> {
+    RangeInclusive::new(memory_map::dma::START, memory_map::dma::END_INCLUSIVE)
+}
+
+/// Translate a physical DRAM address to the address DMA capable devices use for it.
+pub const fn phys_to_bus(phys_addr: usize) -> usize {
+    phys_addr | memory_map::phys::DRAM_BUS_ALIAS
+}
+
+/// Translate the physical address of a device register to the address DMA engines use for it.
+pub const fn mmio_phys_to_bus(phys_addr: usize) -> usize {
+    phys_addr - memory_map::phys::MMIO_BASE + memory_map::phys::PERIPHERAL_BUS_BASE
+}
+
+/// Return the virtual area in which device windows are mapped on demand.
+pub fn ioremap_range() -> RangeInclusive<usize> {
+    RangeInclusive::new(
+        memory_map::ioremap::START,
+        memory_map::ioremap::END_INCLUSIVE,
+    )
+}
+
+/// Return the physical device MMIO window. Everything below it is DRAM.
+pub const fn phys_mmio_range() -> RangeInclusive<usize> {
+    RangeInclusive::new(memory_map::phys::MMIO_BASE, memory_map::phys::END_INCLUSIVE)
+}
+
 /// Return the address space size in bytes.
 pub const fn addr_space_size() -> usize {
-    memory_map::mmio::END_INCLUSIVE + 1
+    memory_map::phys::END_INCLUSIVE + 1
 }
@@ -1,10 +1,93 @@
+/// The kernel runs in the upper half of the address space. Physical address `x` is mapped at
+/// `KERNEL_VIRT_OFFSET + x`, which is the first address translated through TTBR1 with a 4 GiB
+/// upper half.
+pub const KERNEL_VIRT_OFFSET: usize = 0xFFFF_FFFF_0000_0000;
+
+/// Physical address space boundaries.
+#[cfg(feature = "bsp_rpi3")]
+#[rustfmt::skip]
+pub mod phys {
+    pub const MMIO_BASE:       usize =        0x3F00_0000;
+    pub const END_INCLUSIVE:   usize =        0x3FFF_FFFF;
+
+    /// ARM RAM of a Raspberry Pi 3 with the firmware's default GPU memory split. Only used if the
+    /// firmware does not pass a device tree.
+    pub const DEFAULT_RAM_END_INCLUSIVE: usize = 0x3B3F_FFFF;
+
+    /// DMA engines and the VideoCore see DRAM through this uncached bus alias.
+    pub const DRAM_BUS_ALIAS:  usize =        0xC000_0000;
+
+    /// DMA engines see the peripherals at this bus address.
+    pub const PERIPHERAL_BUS_BASE: usize =    0x7E00_0000;
+}
+
+/// Physical address space boundaries. The BCM2711 in its default low peripheral mode places the
+/// peripherals, including the GIC-400, in the last 32 MiB below 4 GiB.
+#[cfg(feature = "bsp_rpi4")]
+#[rustfmt::skip]
+pub mod phys {
+    pub const MMIO_BASE:       usize =        0xFE00_0000;
+    pub const END_INCLUSIVE:   usize =        0xFFFF_FFFF;
+
+    /// The first RAM bank of a Raspberry Pi 4 with the firmware's default GPU memory split. Only
+    /// used if the firmware does not pass a device tree.
+    pub const DEFAULT_RAM_END_INCLUSIVE: usize = 0x3B3F_FFFF;
+
+    /// The legacy DMA engines see the first GiB of DRAM through this bus alias.
+    pub const DRAM_BUS_ALIAS:  usize =        0xC000_0000;
+
+    /// The legacy DMA engines see the peripherals at this bus address.
+    pub const PERIPHERAL_BUS_BASE: usize =    0x7E00_0000;
+}
+
+/// Buffers shared with DMA capable devices, mapped non-cacheable. Placed well above the kernel
+/// image, aligned to the 64 KiB page size.
+#[rustfmt::skip]
+pub mod dma {
+    use super::KERNEL_VIRT_OFFSET;
+
+    pub const START:           usize = KERNEL_VIRT_OFFSET + 0x0100_0000;
+    pub const END_INCLUSIVE:   usize = KERNEL_VIRT_OFFSET + 0x011F_FFFF;
+}
+
+/// The kernel heap starts directly after the DMA region and extends over the rest of the free RAM
+/// it lies in, see `kernel_heap_range()`.
+#[rustfmt::skip]
+pub mod heap {
+    use super::KERNEL_VIRT_OFFSET;
+
+    pub const START:           usize = KERNEL_VIRT_OFFSET + 0x0120_0000;
+}
+
+/// Virtual area for device windows mapped on demand by `memory::mmio::ioremap()`. It reuses the
+/// upper half alias of the MMIO window, which only the boot mapping maps linearly.
+#[rustfmt::skip]
+pub mod ioremap {
+    use super::{phys, KERNEL_VIRT_OFFSET};
+
+    pub const START:           usize = KERNEL_VIRT_OFFSET + phys::MMIO_BASE;
+    pub const END_INCLUSIVE:   usize = KERNEL_VIRT_OFFSET + phys::END_INCLUSIVE;
+}
+
+/// Physical addresses of the devices. Drivers map them with `memory::mmio::ioremap()`.
 #[rustfmt::skip]
 pub mod mmio {
-    pub const BASE:            usize =        0x3F00_0000;
+    use super::phys;
 
+    pub const BASE:            usize = phys::MMIO_BASE;
+
+    pub const DMA_BASE:        usize = BASE + 0x0000_7000;
+    pub const MAILBOX_BASE:    usize = BASE + 0x0000_B880;
     pub const CLOCK_BASE:      usize = BASE + 0x0010_1000;
     pub const GPIO_BASE:       usize = BASE + 0x0020_0000;
     pub const PL011_UART_BASE: usize = BASE + 0x0020_1000;
+    pub const SPI0_BASE:       usize = BASE + 0x0020_4000;
     pub const PWM_BASE:        usize = BASE + 0x0020_C000;
-    pub const END_INCLUSIVE:   usize =        0x3FFF_FFFF;
+    pub const AUX_BASE:        usize = BASE + 0x0021_5000;
+    pub const BSC1_BASE:       usize = BASE + 0x0080_4000;
+
+    #[cfg(feature = "bsp_rpi4")]
+    pub const GICD_BASE:       usize = BASE + 0x0184_1000;
+    #[cfg(feature = "bsp_rpi4")]
+    pub const GICC_BASE:       usize = BASE + 0x0184_2000;
 }
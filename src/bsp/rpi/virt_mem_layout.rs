@@ -1,45 +1,122 @@
 use super::memory_map;
-use crate::memory::*;
+use crate::{arch, const_assert, memory::*};
 use core::ops::RangeInclusive;
 
-pub const NUM_MEM_RANGES: usize = 2;
+extern "C" {
+    static __boot_core_stack_start: usize;
+    static __boot_core_stack_end: usize;
+    static __ro_start: usize;
+    static __ro_end: usize;
+    static __stacks_start: usize;
+    static __stacks_end: usize;
+    static __data_start: usize;
+    static __data_end: usize;
+    static __bss_start: usize;
+    static __bss_end: usize;
+}
 
+pub const NUM_MEM_RANGES: usize = 7;
+
+// Everything fixed at build time is checked before the kernel can boot: the ranges built from
+// constants and the attributes here, the ranges derived from linker symbols by the `ASSERT`s in
+// `link.ld`. Only the heap range depends on the device tree, so `KernelVirtualLayout::validate()`
+// checks the complete layout once more at boot, before it is applied.
+const_assert!(is_page_aligned_range(
+    memory_map::dma::START,
+    memory_map::dma::END_INCLUSIVE,
+    arch::PAGE_SIZE
+));
+const_assert!(is_page_aligned_range(
+    memory_map::ioremap::START,
+    memory_map::ioremap::END_INCLUSIVE,
+    arch::PAGE_SIZE
+));
+const_assert!(memory_map::dma::END_INCLUSIVE < memory_map::heap::START);
+const_assert!(memory_map::heap::START % arch::PAGE_SIZE == 0);
+const_assert!(!KERNEL_RX.is_writable_and_executable());
+const_assert!(!KERNEL_RW.is_writable_and_executable());
+const_assert!(!DMA_RW.is_writable_and_executable());
+
+/// The range between two linker symbols, the second one marking the exclusive end.
+fn linker_range(start: &usize, end_exclusive: &usize) -> RangeInclusive<usize> {
+    #[allow(clippy::range_minus_one)]
+    RangeInclusive::new(
+        start as *const _ as usize,
+        end_exclusive as *const _ as usize - 1,
+    )
+}
+
+/// Normal memory the kernel writes to, which must never be executable.
+const KERNEL_RW: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadWrite,
+    execute_never: true,
+};
+
+/// Kernel code and read-only data.
+const KERNEL_RX: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadOnly,
+    execute_never: false,
+};
+
+/// Buffers shared with DMA capable devices, bypassing the caches.
+const DMA_RW: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::NonCacheable,
+    acc_perms: AccessPermissions::ReadWrite,
+    execute_never: true,
+};
+
+/// Virtual addresses not covered by any of these ranges are left unmapped. Device windows are
+/// mapped on demand into the ioremap area.
 pub static LAYOUT: KernelVirtualLayout<{ NUM_MEM_RANGES }> = KernelVirtualLayout::new(
-    memory_map::mmio::END_INCLUSIVE,
+    memory_map::ioremap::END_INCLUSIVE,
     [
         RangeDescriptor {
-            name: "Kernel code and RO data",
-            virtual_range: || {
-                extern "C" {
-                    static __ro_start: usize;
-                    static __ro_end: usize;
-                }
-                unsafe {
-                    #[allow(clippy::range_minus_one)]
-                    RangeInclusive::new(
-                        &__ro_start as *const _ as usize,
-                        &__ro_end as *const _ as usize - 1,
-                    )
-                }
-            },
-            translation: Translation::Identity,
-            attribute_fields: AttributeFields {
-                mem_attributes: MemAttributes::CacheableDRAM,
-                acc_perms: AccessPermissions::ReadOnly,
-                execute_never: false,
+            name: "Boot core stack and spin tables",
+            virtual_range: || unsafe {
+                linker_range(&__boot_core_stack_start, &__boot_core_stack_end)
             },
+            translation: Translation::KernelLinear,
+            attribute_fields: KERNEL_RW,
         },
         RangeDescriptor {
-            name: "Device MMIO",
+            name: "Kernel code and RO data",
+            virtual_range: || unsafe { linker_range(&__ro_start, &__ro_end) },
+            translation: Translation::KernelLinear,
+            attribute_fields: KERNEL_RX,
+        },
+        RangeDescriptor {
+            name: "Slave core stacks",
+            virtual_range: || unsafe { linker_range(&__stacks_start, &__stacks_end) },
+            translation: Translation::KernelLinear,
+            attribute_fields: KERNEL_RW,
+        },
+        RangeDescriptor {
+            name: "Kernel data",
+            virtual_range: || unsafe { linker_range(&__data_start, &__data_end) },
+            translation: Translation::KernelLinear,
+            attribute_fields: KERNEL_RW,
+        },
+        RangeDescriptor {
+            name: "Kernel BSS and page tables",
+            virtual_range: || unsafe { linker_range(&__bss_start, &__bss_end) },
+            translation: Translation::KernelLinear,
+            attribute_fields: KERNEL_RW,
+        },
+        RangeDescriptor {
+            name: "Kernel heap",
+            virtual_range: super::kernel_heap_range,
+            translation: Translation::KernelLinear,
+            attribute_fields: KERNEL_RW,
+        },
+        RangeDescriptor {
+            name: "DMA coherent buffers",
             virtual_range: || {
-                RangeInclusive::new(memory_map::mmio::BASE, memory_map::mmio::END_INCLUSIVE)
-            },
-            translation: Translation::Identity,
-            attribute_fields: AttributeFields {
-                mem_attributes: MemAttributes::Device,
-                acc_perms: AccessPermissions::ReadWrite,
-                execute_never: true,
+                RangeInclusive::new(memory_map::dma::START, memory_map::dma::END_INCLUSIVE)
             },
+            translation: Translation::KernelLinear,
+            attribute_fields: DMA_RW,
         },
     ],
 );
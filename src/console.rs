@@ -0,0 +1,289 @@
+//! Console multiplexer.
+//!
+//! Output of `print!` and the log macros fans out to every registered sink whose minimum log
+//! level the message reaches. Input comes from a single, selectable source. Sinks and the input
+//! source can be changed at any time.
+
+use crate::{arch::Mutex, interface};
+use core::{fmt, str};
+
+/// Maximum number of sinks.
+const MAX_SINKS: usize = 4;
+
+/// Capacity of a `LogBuffer` in bytes.
+const LOG_BUFFER_SIZE: usize = 4096;
+
+/// Severity of a message, from least to most severe.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Info,
+    Warn,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Info => "Info",
+            LogLevel::Warn => "Warn",
+        })
+    }
+}
+
+/// Reasons the multiplexer rejected a change.
+#[derive(Copy, Clone, Debug)]
+pub enum ConsoleError {
+    /// All `MAX_SINKS` slots are taken.
+    TooManySinks,
+    /// The sink was removed already.
+    NoSuchSink,
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ConsoleError::TooManySinks => "Too many console sinks",
+            ConsoleError::NoSuchSink => "No such console sink",
+        })
+    }
+}
+
+/// Handle of a registered sink, to remove it or change its level.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SinkId(usize);
+
+#[derive(Copy, Clone)]
+struct Sink {
+    writer: &'static dyn interface::console::Write,
+    min_level: LogLevel,
+}
+
+#[derive(Copy, Clone)]
+struct Source {
+    reader: &'static dyn interface::console::Read,
+}
+
+// Sinks and sources lock their own state, so any core may use them.
+unsafe impl Send for Sink {}
+unsafe impl Send for Source {}
+
+pub struct Console {
+    sinks: Mutex<[Option<Sink>; MAX_SINKS]>,
+    input: Mutex<Option<Source>>,
+}
+
+#[allow(dead_code)]
+impl Console {
+    const fn new() -> Self {
+        Console {
+            sinks: Mutex::new([None; MAX_SINKS]),
+            input: Mutex::new(None),
+        }
+    }
+
+    /// Send messages of `min_level` and above to `writer`.
+    pub fn add_sink(
+        &self,
+        writer: &'static dyn interface::console::Write,
+        min_level: LogLevel,
+    ) -> Result<SinkId, ConsoleError> {
+        let mut sinks = self.sinks.lock();
+        let index = sinks
+            .iter()
+            .position(Option::is_none)
+            .ok_or(ConsoleError::TooManySinks)?;
+
+        sinks[index] = Some(Sink { writer, min_level });
+        Ok(SinkId(index))
+    }
+
+    /// Stop sending messages to the sink. It is flushed first.
+    pub fn remove_sink(&self, id: SinkId) -> Result<(), ConsoleError> {
+        let sink = self.sinks.lock()[id.0]
+            .take()
+            .ok_or(ConsoleError::NoSuchSink)?;
+
+        sink.writer.flush();
+        Ok(())
+    }
+
+    pub fn set_min_level(&self, id: SinkId, min_level: LogLevel) -> Result<(), ConsoleError> {
+        let mut sinks = self.sinks.lock();
+        let sink = sinks[id.0].as_mut().ok_or(ConsoleError::NoSuchSink)?;
+
+        sink.min_level = min_level;
+        Ok(())
+    }
+
+    /// Read input from `reader` from now on.
+    pub fn set_input(&self, reader: &'static dyn interface::console::Read) {
+        *self.input.lock() = Some(Source { reader });
+    }
+
+    /// Write `args` to every sink that takes messages of `level`.
+    ///
+    /// The sinks are written without holding the lock, so a slow sink does not block changes to
+    /// the sink list.
+    pub fn write_log(&self, level: LogLevel, args: fmt::Arguments) -> fmt::Result {
+        let sinks = *self.sinks.lock();
+
+        sinks
+            .iter()
+            .flatten()
+            .filter(|sink| level >= sink.min_level)
+            .try_for_each(|sink| sink.writer.write_fmt(args))
+    }
+
+    fn for_each_info_sink(&self, f: impl Fn(&dyn interface::console::Write)) {
+        let sinks = *self.sinks.lock();
+
+        for sink in sinks.iter().flatten() {
+            if LogLevel::Info >= sink.min_level {
+                f(sink.writer);
+            }
+        }
+    }
+}
+
+/// Plain output goes to the sinks that take `LogLevel::Info`.
+impl interface::console::Write for Console {
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.write_log(LogLevel::Info, args)
+    }
+
+    fn write_char(&self, c: char) {
+        self.for_each_info_sink(|writer| writer.write_char(c));
+    }
+
+    fn flush(&self) {
+        self.for_each_info_sink(|writer| writer.flush());
+    }
+}
+
+/// Without an input source, reading returns the trait's default character.
+impl interface::console::Read for Console {
+    fn read_char(&self) -> char {
+        let input = *self.input.lock();
+
+        match input {
+            Some(source) => source.reader.read_char(),
+            None => ' ',
+        }
+    }
+
+    fn clear(&self) {
+        let input = *self.input.lock();
+
+        if let Some(source) = input {
+            source.reader.clear();
+        }
+    }
+}
+
+struct LogBufferInner {
+    data: [u8; LOG_BUFFER_SIZE],
+    /// Index the next byte goes to.
+    head: usize,
+    /// Whether the buffer has wrapped, so that `data[head..]` holds the oldest bytes.
+    wrapped: bool,
+}
+
+/// A sink that keeps the most recent `LOG_BUFFER_SIZE` bytes of output in memory.
+pub struct LogBuffer {
+    inner: Mutex<LogBufferInner>,
+}
+
+#[allow(dead_code)]
+impl LogBuffer {
+    pub const fn new() -> Self {
+        LogBuffer {
+            inner: Mutex::new(LogBufferInner {
+                data: [0; LOG_BUFFER_SIZE],
+                head: 0,
+                wrapped: false,
+            }),
+        }
+    }
+
+    /// Number of bytes held.
+    pub fn len(&self) -> usize {
+        let inner = self.inner.lock();
+
+        if inner.wrapped {
+            LOG_BUFFER_SIZE
+        } else {
+            inner.head
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replay the buffered output, oldest first, e.g. into a sink added late.
+    ///
+    /// The buffer is copied first, so `writer` may be a console this buffer is a sink of.
+    pub fn write_to(&self, writer: &dyn interface::console::Write) {
+        let (data, head, wrapped) = {
+            let inner = self.inner.lock();
+            (inner.data, inner.head, inner.wrapped)
+        };
+        let (newer, older) = data.split_at(head);
+
+        if wrapped {
+            write_bytes(writer, older);
+        }
+        write_bytes(writer, newer);
+    }
+}
+
+/// Write `bytes` as text. The oldest character of a wrapped buffer may be cut, so bytes that are
+/// not valid UTF-8 are written one by one.
+fn write_bytes(writer: &dyn interface::console::Write, bytes: &[u8]) {
+    match str::from_utf8(bytes) {
+        Ok(s) => {
+            let _ = writer.write_fmt(format_args!("{}", s));
+        }
+        Err(_) => bytes.iter().for_each(|&b| writer.write_char(b as char)),
+    }
+}
+
+impl LogBufferInner {
+    fn push(&mut self, byte: u8) {
+        self.data[self.head] = byte;
+        self.head += 1;
+
+        if self.head == LOG_BUFFER_SIZE {
+            self.head = 0;
+            self.wrapped = true;
+        }
+    }
+}
+
+impl fmt::Write for LogBufferInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|b| self.push(b));
+
+        Ok(())
+    }
+}
+
+impl interface::console::Write for LogBuffer {
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        fmt::Write::write_fmt(&mut *self.inner.lock(), args)
+    }
+
+    /// Characters are stored as their UTF-8 bytes.
+    fn write_char(&self, c: char) {
+        let mut buf = [0; 4];
+        let _ = fmt::Write::write_str(&mut *self.inner.lock(), c.encode_utf8(&mut buf));
+    }
+
+    fn flush(&self) {}
+}
+
+static CONSOLE: Console = Console::new();
+
+/// Return a reference to the console multiplexer.
+pub fn console() -> &'static Console {
+    &CONSOLE
+}
@@ -0,0 +1,229 @@
+//! Registry of the device drivers of the board.
+//!
+//! The BSP registers its drivers together with optional post-init hooks. `init_drivers()` then
+//! brings them up in dependency order and records for each one whether it succeeded, so a failing
+//! driver only takes down the drivers depending on it.
+
+use crate::{fdt, interface};
+use core::fmt;
+
+/// Maximum number of drivers the manager keeps.
+const MAX_DRIVERS: usize = 16;
+
+/// Called right after the driver's `init()` succeeded, e.g. to route the device's pins.
+pub type DeviceDriverPostInitCallback = unsafe fn() -> interface::driver::Result;
+
+/// What became of a registered driver.
+#[derive(Copy, Clone)]
+pub enum DriverStatus {
+    /// Not initialized yet.
+    Registered,
+    Initialized,
+    /// `init()` or the post-init hook returned an error.
+    Failed(interface::driver::Error),
+    /// Not initialized, because no registered driver has the named `compatible()`.
+    MissingDependency(&'static str),
+    /// Not initialized, because the named dependency could not be initialized.
+    DependencyFailed(&'static str),
+    /// Not initialized, because the driver's dependencies lead back to it or to another cycle.
+    DependencyCycle,
+}
+
+impl fmt::Display for DriverStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DriverStatus::Registered => f.write_str("Not initialized"),
+            DriverStatus::Initialized => f.write_str("Initialized"),
+            DriverStatus::Failed(err) => write!(f, "Failed: {}", err),
+            DriverStatus::MissingDependency(name) => write!(f, "Missing dependency {}", name),
+            DriverStatus::DependencyFailed(name) => write!(f, "Dependency {} failed", name),
+            DriverStatus::DependencyCycle => f.write_str("Dependency cycle"),
+        }
+    }
+}
+
+/// A registered driver and what the manager knows about it.
+pub struct DeviceDriverDescriptor {
+    driver: &'static mut dyn interface::driver::DeviceDriver,
+    post_init_callback: Option<DeviceDriverPostInitCallback>,
+    node: Option<fdt::Node<'static>>,
+    status: DriverStatus,
+}
+
+impl DeviceDriverDescriptor {
+    pub fn new(
+        driver: &'static mut dyn interface::driver::DeviceDriver,
+        post_init_callback: Option<DeviceDriverPostInitCallback>,
+    ) -> Self {
+        Self {
+            driver,
+            post_init_callback,
+            node: None,
+            status: DriverStatus::Registered,
+        }
+    }
+
+    pub fn driver(&self) -> &dyn interface::driver::DeviceDriver {
+        &*self.driver
+    }
+
+    /// The driver, to be pointed at its device before `init_drivers()`.
+    pub fn driver_mut(&mut self) -> &mut dyn interface::driver::DeviceDriver {
+        &mut *self.driver
+    }
+
+    /// The device tree node the driver was matched against, if any.
+    pub fn node(&self) -> Option<fdt::Node<'static>> {
+        self.node
+    }
+
+    pub fn set_node(&mut self, node: Option<fdt::Node<'static>>) {
+        self.node = node;
+    }
+
+    pub fn status(&self) -> DriverStatus {
+        self.status
+    }
+
+    /// Initialize the driver and run its post-init hook.
+    unsafe fn init(&mut self) {
+        let result = self
+            .driver
+            .init()
+            .and_then(|()| match self.post_init_callback {
+                Some(callback) => callback(),
+                None => Ok(()),
+            });
+
+        self.status = match result {
+            Ok(()) => DriverStatus::Initialized,
+            Err(err) => DriverStatus::Failed(err),
+        };
+    }
+}
+
+pub struct DriverManager {
+    descriptors: [Option<DeviceDriverDescriptor>; MAX_DRIVERS],
+    len: usize,
+}
+
+impl DriverManager {
+    const fn new() -> Self {
+        Self {
+            descriptors: [
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None,
+            ],
+            len: 0,
+        }
+    }
+
+    /// Add a driver. Drivers without dependencies between them are initialized in the order they
+    /// were registered.
+    ///
+    /// # Panics
+    ///
+    /// - If more than `MAX_DRIVERS` drivers are registered.
+    pub fn register(&mut self, descriptor: DeviceDriverDescriptor) {
+        if self.len == MAX_DRIVERS {
+            panic!(
+                "Too many device drivers: {}",
+                descriptor.driver().compatible()
+            );
+        }
+
+        self.descriptors[self.len] = Some(descriptor);
+        self.len += 1;
+    }
+
+    pub fn descriptors(&self) -> impl Iterator<Item = &DeviceDriverDescriptor> {
+        self.descriptors[..self.len].iter().flatten()
+    }
+
+    pub fn descriptors_mut(&mut self) -> impl Iterator<Item = &mut DeviceDriverDescriptor> {
+        self.descriptors[..self.len].iter_mut().flatten()
+    }
+
+    /// The status the dependency named `compatible` imposes on the drivers depending on it.
+    /// `None` if it is initialized, `Some(DriverStatus::Registered)` if it is not yet.
+    fn dependency_status(&self, compatible: &'static str) -> Option<DriverStatus> {
+        let dependency = self
+            .descriptors()
+            .find(|descriptor| descriptor.driver().compatible() == compatible);
+
+        match dependency.map(DeviceDriverDescriptor::status) {
+            None => Some(DriverStatus::MissingDependency(compatible)),
+            Some(DriverStatus::Initialized) => None,
+            Some(DriverStatus::Registered) => Some(DriverStatus::Registered),
+            Some(_) => Some(DriverStatus::DependencyFailed(compatible)),
+        }
+    }
+
+    /// The status of the driver at `index` if it cannot be initialized yet or at all, from the
+    /// first of its dependencies that is not initialized.
+    fn blocked_status(&self, index: usize) -> Option<DriverStatus> {
+        let descriptor = self.descriptors[index].as_ref()?;
+
+        descriptor
+            .driver()
+            .dependencies()
+            .iter()
+            .filter_map(|&dependency| self.dependency_status(dependency))
+            .next()
+    }
+
+    /// Initialize every registered driver after the drivers it depends on. Drivers whose
+    /// dependencies are missing, failed or form a cycle are left alone. The outcome for each
+    /// driver is its `status()`.
+    ///
+    /// # Safety
+    ///
+    /// - Must be called once, after the drivers were pointed at their devices.
+    pub unsafe fn init_drivers(&mut self) {
+        loop {
+            let mut progress = false;
+
+            for index in 0..self.len {
+                let ready = match self.descriptors[index].as_ref().map(|d| d.status()) {
+                    Some(DriverStatus::Registered) => self.blocked_status(index),
+                    _ => continue,
+                };
+
+                let descriptor = self.descriptors[index].as_mut().unwrap();
+                match ready {
+                    None => descriptor.init(),
+                    Some(DriverStatus::Registered) => continue,
+                    Some(status) => descriptor.status = status,
+                }
+                progress = true;
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        // Whatever is still waiting is stuck on a cycle.
+        for descriptor in self.descriptors_mut() {
+            if let DriverStatus::Registered = descriptor.status {
+                descriptor.status = DriverStatus::DependencyCycle;
+            }
+        }
+    }
+}
+
+static mut DRIVER_MANAGER: DriverManager = DriverManager::new();
+
+/// Return a reference to the driver manager.
+pub fn driver_manager() -> &'static DriverManager {
+    unsafe { &DRIVER_MANAGER }
+}
+
+/// Return a mutable reference to the driver manager.
+///
+/// # Safety
+///
+/// - Only for registering and initializing the drivers, while the boot core runs alone.
+pub unsafe fn driver_manager_mut() -> &'static mut DriverManager {
+    &mut DRIVER_MANAGER
+}
@@ -0,0 +1,709 @@
+//! Reader for the flattened device tree blob (DTB) the firmware hands to the kernel.
+
+use crate::memory;
+use core::{fmt, ptr};
+use spin::Once;
+
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+/// Oldest blob version whose layout the reader understands.
+const FDT_LAST_COMP_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Size of the header fields the reader uses.
+const HEADER_SIZE: usize = 40;
+
+/// Deepest node nesting the reader accepts.
+const MAX_DEPTH: usize = 16;
+
+/// The firmware places the blob in memory the kernel later reuses, so it is copied into the
+/// kernel image. The Raspberry Pi blobs are around 30 KiB, QEMU's can be up to 1 MiB.
+const MAX_BLOB_SIZE: usize = 1024 * 1024;
+
+#[derive(Copy, Clone, Debug)]
+pub enum FdtError {
+    /// The firmware did not pass a blob.
+    Missing,
+    BadMagic,
+    UnsupportedVersion(u32),
+    TooLarge(usize),
+    /// An offset or length points outside of the blob.
+    Truncated,
+    /// The structure block contains an unknown token or unbalanced nodes.
+    BadStructure,
+}
+
+impl fmt::Display for FdtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FdtError::Missing => write!(f, "No device tree passed by the firmware"),
+            FdtError::BadMagic => write!(f, "Bad device tree magic"),
+            FdtError::UnsupportedVersion(v) => write!(f, "Unsupported device tree version {}", v),
+            FdtError::TooLarge(size) => write!(f, "Device tree too large ({} bytes)", size),
+            FdtError::Truncated => write!(f, "Device tree truncated"),
+            FdtError::BadStructure => write!(f, "Malformed device tree structure"),
+        }
+    }
+}
+
+/// The `len` bytes at `offset`. Offsets and lengths come from the blob, so the end is computed
+/// without overflowing.
+fn bytes_at(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], FdtError> {
+    let end = offset.checked_add(len).ok_or(FdtError::Truncated)?;
+
+    bytes.get(offset..end).ok_or(FdtError::Truncated)
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Result<u32, FdtError> {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes_at(bytes, offset, 4)?);
+
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn be64(bytes: &[u8], offset: usize) -> Result<u64, FdtError> {
+    let offset_low = offset.checked_add(4).ok_or(FdtError::Truncated)?;
+
+    Ok((u64::from(be32(bytes, offset)?) << 32) | u64::from(be32(bytes, offset_low)?))
+}
+
+/// The NUL-terminated string at `offset`.
+fn c_str(bytes: &[u8], offset: usize) -> Result<&str, FdtError> {
+    let bytes = bytes.get(offset..).ok_or(FdtError::Truncated)?;
+    let len = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(FdtError::Truncated)?;
+
+    core::str::from_utf8(&bytes[..len]).map_err(|_| FdtError::BadStructure)
+}
+
+/// Read a number that spans `cells` 32-bit cells.
+fn read_cells(bytes: &[u8], offset: usize, cells: u32) -> Result<u64, FdtError> {
+    match cells {
+        1 => Ok(u64::from(be32(bytes, offset)?)),
+        2 => be64(bytes, offset),
+        _ => Err(FdtError::BadStructure),
+    }
+}
+
+/// Iterate over the (address, size) pairs of a `reg` property.
+fn reg_entries(
+    value: &[u8],
+    address_cells: u32,
+    size_cells: u32,
+) -> impl Iterator<Item = (u64, u64)> + '_ {
+    let entry_size = ((address_cells + size_cells) * 4) as usize;
+
+    (0..value.len() / entry_size.max(1)).filter_map(move |i| {
+        let offset = i * entry_size;
+        let address = read_cells(value, offset, address_cells).ok()?;
+        let size = read_cells(value, offset + address_cells as usize * 4, size_cells).ok()?;
+
+        Some((address, size))
+    })
+}
+
+/// Iterate over the (child address, parent address, size) triples of a `ranges` property.
+fn ranges_entries(
+    value: &[u8],
+    child_cells: u32,
+    parent_cells: u32,
+    size_cells: u32,
+) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+    let entry_size = ((child_cells + parent_cells + size_cells) * 4) as usize;
+
+    (0..value.len() / entry_size.max(1)).filter_map(move |i| {
+        let offset = i * entry_size;
+        let child = read_cells(value, offset, child_cells).ok()?;
+        let offset = offset + child_cells as usize * 4;
+        let parent = read_cells(value, offset, parent_cells).ok()?;
+        let offset = offset + parent_cells as usize * 4;
+        let size = read_cells(value, offset, size_cells).ok()?;
+
+        Some((child, parent, size))
+    })
+}
+
+/// Translate `address` from the address space of `bus`'s children to the CPU's, through the
+/// `ranges` of `bus` and its ancestors. `None` if a bus has no `ranges` or none of them covers the
+/// address.
+fn translate_address(mut bus: Option<Node>, mut address: u64) -> Option<u64> {
+    while let Some(node) = bus {
+        // Addresses of the root's children are CPU addresses.
+        let parent = match node.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+
+        // An empty `ranges` means the bus uses the same addresses as its parent.
+        let ranges = node.property("ranges")?;
+        if !ranges.value().is_empty() {
+            address = ranges_entries(
+                ranges.value(),
+                node.address_cells(),
+                parent.address_cells(),
+                node.size_cells(),
+            )
+            .find(|&(child, _, size)| address >= child && address - child < size)
+            .map(|(child, parent, _)| parent + (address - child))?;
+        }
+
+        bus = Some(parent);
+    }
+
+    Some(address)
+}
+
+/// One element of the structure block.
+#[derive(Copy, Clone)]
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Property(Property<'a>),
+}
+
+/// Walks the structure block. Only used on blobs that `DeviceTree::new()` has checked, so it
+/// simply ends on anything unexpected.
+struct Tokens<'a> {
+    structs: &'a [u8],
+    strings: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Tokens<'a> {
+    /// Return the next token and the offset it starts at.
+    fn next_token(&mut self) -> Result<Option<(usize, Token<'a>)>, FdtError> {
+        loop {
+            let start = self.offset;
+            let token = be32(self.structs, self.offset)?;
+            self.offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(self.structs, self.offset)?;
+                    self.offset = (self.offset + name.len() + 1 + 3) & !3;
+
+                    return Ok(Some((start, Token::BeginNode(name))));
+                }
+                FDT_END_NODE => return Ok(Some((start, Token::EndNode))),
+                FDT_PROP => {
+                    let len = be32(self.structs, self.offset)? as usize;
+                    let name_offset = be32(self.structs, self.offset + 4)? as usize;
+                    let value_start = self.offset + 8;
+                    let value = bytes_at(self.structs, value_start, len)?;
+                    self.offset = (value_start + len + 3) & !3;
+
+                    let name = c_str(self.strings, name_offset)?;
+                    return Ok(Some((start, Token::Property(Property { name, value }))));
+                }
+                FDT_NOP => continue,
+                FDT_END => return Ok(None),
+                _ => return Err(FdtError::BadStructure),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = (usize, Token<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().unwrap_or(None)
+    }
+}
+
+/// A property of a node.
+#[derive(Copy, Clone)]
+pub struct Property<'a> {
+    name: &'a str,
+    value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// The value as a single cell, or the first cell of a longer value.
+    pub fn as_u32(&self) -> Option<u32> {
+        be32(self.value, 0).ok()
+    }
+
+    /// The value as a string, or the first string of a string list.
+    pub fn as_str(&self) -> Option<&'a str> {
+        c_str(self.value, 0).ok()
+    }
+
+    /// The strings of a string list, like `compatible`.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+}
+
+/// An interrupt specifier of a node's `interrupts` property. Its meaning depends on the interrupt
+/// controller, e.g. (bank, number) for the BCM2835 controller.
+#[derive(Copy, Clone)]
+pub struct InterruptSpecifier<'a>(&'a [u8]);
+
+impl<'a> InterruptSpecifier<'a> {
+    pub fn cells(&self) -> impl Iterator<Item = u32> + 'a {
+        let cells = self.0;
+
+        (0..cells.len() / 4).filter_map(move |i| be32(cells, i * 4).ok())
+    }
+}
+
+impl fmt::Display for InterruptSpecifier<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<")?;
+        for (i, cell) in self.cells().enumerate() {
+            if i != 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:#x}", cell)?;
+        }
+        write!(f, ">")
+    }
+}
+
+/// A node of a validated device tree.
+#[derive(Copy, Clone)]
+pub struct Node<'a> {
+    tree: &'a DeviceTree<'a>,
+    /// Offset of the node's begin token in the structure block.
+    offset: usize,
+    name: &'a str,
+}
+
+impl<'a> Node<'a> {
+    /// The full name, including the unit address. Empty for the root node.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The name without the unit address, e.g. `serial` for `serial@7e201000`.
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    /// The tokens after the node's begin token, up to the end of the structure block.
+    fn contents(&self) -> Tokens<'a> {
+        let mut tokens = self.tree.tokens_at(self.offset);
+        tokens.next();
+        tokens
+    }
+
+    pub fn properties(&self) -> impl Iterator<Item = Property<'a>> {
+        // The properties of a node come before its children.
+        self.contents().scan((), |_, (_, token)| match token {
+            Token::Property(property) => Some(property),
+            _ => None,
+        })
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = Node<'a>> {
+        let tree = self.tree;
+
+        self.contents()
+            .scan(0_usize, move |depth, (offset, token)| match token {
+                Token::BeginNode(name) => {
+                    *depth += 1;
+                    if *depth == 1 {
+                        Some(Some(Node { tree, offset, name }))
+                    } else {
+                        Some(None)
+                    }
+                }
+                // The end of this node itself ends the iteration.
+                Token::EndNode => {
+                    *depth = depth.checked_sub(1)?;
+                    Some(None)
+                }
+                Token::Property(_) => Some(None),
+            })
+            .flatten()
+    }
+
+    pub fn parent(&self) -> Option<Node<'a>> {
+        self.tree.parent_of(self.offset)
+    }
+
+    /// Number of cells of the addresses in the children's `reg`.
+    pub fn address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|property| property.as_u32())
+            .unwrap_or(2)
+    }
+
+    /// Number of cells of the sizes in the children's `reg`.
+    pub fn size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|property| property.as_u32())
+            .unwrap_or(1)
+    }
+
+    /// The (address, size) pairs of the `reg` property, with the addresses translated to CPU
+    /// physical addresses. Entries the buses above the node do not map are skipped.
+    pub fn reg(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let parent = self.parent();
+        let (address_cells, size_cells) = parent.map_or((2, 1), |parent| {
+            (parent.address_cells(), parent.size_cells())
+        });
+        let value = self
+            .property("reg")
+            .map_or(&[][..], |property| property.value);
+
+        reg_entries(value, address_cells, size_cells)
+            .filter_map(move |(address, size)| Some((translate_address(parent, address)?, size)))
+    }
+
+    pub fn compatible(&self) -> impl Iterator<Item = &'a str> {
+        self.property("compatible")
+            .into_iter()
+            .flat_map(|property| property.strings())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    /// Whether the `status` property is absent or says the device is usable.
+    pub fn is_enabled(&self) -> bool {
+        match self
+            .property("status")
+            .and_then(|property| property.as_str())
+        {
+            None | Some("okay") | Some("ok") => true,
+            Some(_) => false,
+        }
+    }
+
+    /// The interrupt controller the node's interrupts go to, from its own `interrupt-parent` or
+    /// the nearest ancestor's.
+    pub fn interrupt_parent(&self) -> Option<Node<'a>> {
+        let mut node = Some(*self);
+        while let Some(current) = node {
+            if let Some(property) = current.property("interrupt-parent") {
+                return self.tree.find_phandle(property.as_u32()?);
+            }
+            node = current.parent();
+        }
+
+        None
+    }
+
+    /// The specifiers of the `interrupts` property, split according to the `#interrupt-cells` of
+    /// the interrupt parent.
+    pub fn interrupts(&self) -> impl Iterator<Item = InterruptSpecifier<'a>> {
+        let cells = self
+            .interrupt_parent()
+            .and_then(|parent| parent.property("#interrupt-cells"))
+            .and_then(|property| property.as_u32())
+            .unwrap_or(1)
+            .max(1) as usize;
+        let value = self
+            .property("interrupts")
+            .map_or(&[][..], |property| property.value);
+
+        value.chunks_exact(cells * 4).map(InterruptSpecifier)
+    }
+}
+
+/// The `/chosen` node, through which the firmware passes boot parameters.
+#[derive(Copy, Clone)]
+pub struct Chosen<'a>(Node<'a>);
+
+impl<'a> Chosen<'a> {
+    /// The kernel command line.
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.0
+            .property("bootargs")
+            .and_then(|property| property.as_str())
+    }
+
+    /// The path or alias of the console, without options, e.g. `serial0` for
+    /// `serial0:115200n8`.
+    pub fn stdout_path(&self) -> Option<&'a str> {
+        let path = self
+            .0
+            .property("stdout-path")
+            .or_else(|| self.0.property("linux,stdout-path"))
+            .and_then(|property| property.as_str())?;
+
+        path.split(':').next()
+    }
+
+    /// The console node.
+    pub fn stdout(&self) -> Option<Node<'a>> {
+        self.0.tree.find_node(self.stdout_path()?)
+    }
+}
+
+/// A validated device tree blob.
+pub struct DeviceTree<'a> {
+    blob: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    mem_rsvmap_offset: usize,
+}
+
+impl<'a> DeviceTree<'a> {
+    /// Check the header and the structure block of `blob`.
+    pub fn new(blob: &'a [u8]) -> Result<Self, FdtError> {
+        if be32(blob, 0)? != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+
+        let last_comp_version = be32(blob, 24)?;
+        if last_comp_version > FDT_LAST_COMP_VERSION {
+            return Err(FdtError::UnsupportedVersion(last_comp_version));
+        }
+
+        let total_size = be32(blob, 4)? as usize;
+        let off_dt_struct = be32(blob, 8)? as usize;
+        let off_dt_strings = be32(blob, 12)? as usize;
+        let off_mem_rsvmap = be32(blob, 16)? as usize;
+        let size_dt_strings = be32(blob, 32)? as usize;
+        let size_dt_struct = be32(blob, 36)? as usize;
+
+        let blob = blob.get(..total_size).ok_or(FdtError::Truncated)?;
+        let tree = DeviceTree {
+            blob,
+            structs: bytes_at(blob, off_dt_struct, size_dt_struct)?,
+            strings: bytes_at(blob, off_dt_strings, size_dt_strings)?,
+            mem_rsvmap_offset: off_mem_rsvmap,
+        };
+
+        // Walk the whole structure once, so that later walks cannot fail. There must be exactly
+        // one root node.
+        let mut tokens = tree.tokens_at(0);
+        let mut depth = 0_usize;
+        let mut roots = 0;
+        while let Some((_, token)) = tokens.next_token()? {
+            match token {
+                Token::BeginNode(_) if depth == MAX_DEPTH => return Err(FdtError::BadStructure),
+                Token::BeginNode(_) => {
+                    roots += (depth == 0) as usize;
+                    depth += 1;
+                }
+                Token::EndNode => depth = depth.checked_sub(1).ok_or(FdtError::BadStructure)?,
+                Token::Property(_) if depth == 0 => return Err(FdtError::BadStructure),
+                Token::Property(_) => (),
+            }
+        }
+        if depth != 0 || roots != 1 {
+            return Err(FdtError::BadStructure);
+        }
+
+        Ok(tree)
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.blob.len()
+    }
+
+    fn tokens_at(&self, offset: usize) -> Tokens<'a> {
+        Tokens {
+            structs: self.structs,
+            strings: self.strings,
+            offset,
+        }
+    }
+
+    pub fn root(&self) -> Node<'_> {
+        self.nodes()
+            .next()
+            .expect("Validated device tree has a root node")
+    }
+
+    /// All nodes, depth first.
+    pub fn nodes(&self) -> impl Iterator<Item = Node<'_>> {
+        self.tokens_at(0)
+            .filter_map(move |(offset, token)| match token {
+                Token::BeginNode(name) => Some(Node {
+                    tree: self,
+                    offset,
+                    name,
+                }),
+                _ => None,
+            })
+    }
+
+    /// The parent of the node whose begin token is at `offset`.
+    fn parent_of(&self, offset: usize) -> Option<Node<'_>> {
+        let mut ancestors = [None; MAX_DEPTH];
+        let mut depth = 0;
+
+        for (token_offset, token) in self.tokens_at(0) {
+            match token {
+                Token::BeginNode(name) => {
+                    if token_offset == offset {
+                        return if depth == 0 {
+                            None
+                        } else {
+                            ancestors[depth - 1]
+                        };
+                    }
+                    ancestors[depth] = Some(Node {
+                        tree: self,
+                        offset: token_offset,
+                        name,
+                    });
+                    depth += 1;
+                }
+                Token::EndNode => depth -= 1,
+                Token::Property(_) => (),
+            }
+        }
+
+        None
+    }
+
+    /// Look up a node by its path, e.g. `/soc/gpio@7e200000`, or by an alias from `/aliases`.
+    /// Path components without a unit address also match nodes that have one.
+    pub fn find_node(&self, path: &str) -> Option<Node<'_>> {
+        if !path.starts_with('/') {
+            let alias = self.find_node("/aliases")?.property(path)?.as_str()?;
+            return if alias.starts_with('/') {
+                self.find_node(alias)
+            } else {
+                None
+            };
+        }
+
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self.root(), |node, component| {
+                node.children().find(|child| {
+                    child.name() == component
+                        || (!component.contains('@') && child.base_name() == component)
+                })
+            })
+    }
+
+    /// All nodes whose `compatible` lists `compatible`.
+    pub fn find_compatible<'s, 'c>(
+        &'s self,
+        compatible: &'c str,
+    ) -> impl Iterator<Item = Node<'s>> + 'c
+    where
+        's: 'c,
+    {
+        self.nodes()
+            .filter(move |node| node.is_compatible(compatible))
+    }
+
+    /// The node a phandle refers to.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'_>> {
+        self.nodes().find(|node| {
+            node.property("phandle")
+                .or_else(|| node.property("linux,phandle"))
+                .and_then(|property| property.as_u32())
+                == Some(phandle)
+        })
+    }
+
+    pub fn chosen(&self) -> Option<Chosen<'_>> {
+        self.find_node("/chosen").map(Chosen)
+    }
+
+    /// Call `f` with the (address, size) of every entry of the memory reservation block.
+    fn for_each_mem_rsvmap_entry(&self, f: &mut dyn FnMut(u64, u64)) {
+        let mut offset = self.mem_rsvmap_offset;
+
+        // The block ends with an all-zero entry.
+        while let (Ok(address), Ok(size)) = (
+            be64(self.blob, offset),
+            be64(self.blob, offset.saturating_add(8)),
+        ) {
+            if address == 0 && size == 0 {
+                break;
+            }
+
+            f(address, size);
+            offset += 16;
+        }
+    }
+
+    /// Call `f` with the (address, size) of every RAM bank listed in the `/memory` nodes.
+    pub fn for_each_memory_region(&self, f: &mut dyn FnMut(u64, u64)) {
+        self.root()
+            .children()
+            .filter(|node| node.base_name() == "memory")
+            .flat_map(|node| node.reg())
+            .for_each(|(address, size)| f(address, size));
+    }
+
+    /// Call `f` with the (address, size) of every region the kernel must not use, from both the
+    /// memory reservation block and the children of `/reserved-memory`.
+    pub fn for_each_reserved_region(&self, f: &mut dyn FnMut(u64, u64)) {
+        self.for_each_mem_rsvmap_entry(f);
+
+        self.find_node("/reserved-memory")
+            .into_iter()
+            .flat_map(|node| node.children())
+            .flat_map(|node| node.reg())
+            .for_each(|(address, size)| f(address, size));
+    }
+}
+
+/// Room for the copy of the firmware's blob.
+#[repr(align(8))]
+struct BlobBuffer([u8; MAX_BLOB_SIZE]);
+
+static mut BLOB_BUFFER: BlobBuffer = BlobBuffer([0; MAX_BLOB_SIZE]);
+
+static DEVICE_TREE: Once<Result<DeviceTree<'static>, FdtError>> = Once::new();
+
+/// Copy and check the blob the firmware passed at `phys_addr`, if any. Failures are reported by
+/// `device_tree()`.
+///
+/// # Safety
+///
+/// - Must run before the boot mapping is refined, which still maps all of RAM.
+/// - Must only be called once, by the boot core.
+pub unsafe fn init(phys_addr: Option<usize>) {
+    DEVICE_TREE.call_once(|| {
+        let src = memory::kernel_phys_to_virt(phys_addr.ok_or(FdtError::Missing)?) as *const u8;
+
+        let mut header = [0_u8; HEADER_SIZE];
+        ptr::copy_nonoverlapping(src, header.as_mut_ptr(), HEADER_SIZE);
+        if be32(&header, 0)? != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+
+        let total_size = be32(&header, 4)? as usize;
+        if total_size > MAX_BLOB_SIZE {
+            return Err(FdtError::TooLarge(total_size));
+        }
+
+        ptr::copy_nonoverlapping(src, BLOB_BUFFER.0.as_mut_ptr(), total_size);
+        DeviceTree::new(&BLOB_BUFFER.0[..total_size])
+    });
+}
+
+/// The firmware's device tree, or why there is none.
+pub fn device_tree() -> Result<&'static DeviceTree<'static>, FdtError> {
+    match DEVICE_TREE.r#try() {
+        Some(Ok(tree)) => Ok(tree),
+        Some(Err(err)) => Err(*err),
+        None => Err(FdtError::Missing),
+    }
+}
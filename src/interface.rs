@@ -32,23 +32,155 @@ pub mod console {
 }
 
 pub mod pwm {
+    use core::fmt;
+
+    /// One of the two PWM outputs.
     #[allow(dead_code)]
-    pub trait Set {
-        fn set_mode(&self, mode: u32);
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Channel {
+        Ch1,
+        Ch2,
+    }
 
-        fn set_range(&self, range: u32);
+    /// How a channel spreads the high time over a period.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Mode {
+        /// One high pulse per period, as long as the duty cycle.
+        MarkSpace,
+        /// Short pulses spread evenly over the period, high for the duty cycle in total.
+        Balanced,
+    }
 
-        fn set_clock(&self, divisor: u32);
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Polarity {
+        /// High for the duty cycle.
+        Normal,
+        /// Low for the duty cycle.
+        Inverted,
     }
 
-    pub trait Output {
-        fn write(&self, pin: u32, value: u32);
+    /// How a streaming channel sends the words of the FIFO.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum StreamMode {
+        /// Each word is the duty of one period, in clock cycles out of the range.
+        Pwm,
+        /// Each word is sent bit by bit, most significant bit first, one bit per clock cycle.
+        Serialiser,
     }
 
-    pub trait All = Set + Output;
+    /// What a streaming channel sends when the FIFO runs empty.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Underrun {
+        /// Repeat the last word until new ones arrive. Running empty is not an error.
+        RepeatLast,
+        /// Rest at the silence level, and report `Error::FifoUnderrun`.
+        Silence,
+    }
+
+    /// Reasons a PWM request was rejected.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Error {
+        /// The frequency is 0 or above the PWM clock.
+        InvalidFrequency(u32),
+        /// The duty cycle is not between 0.0 and 1.0.
+        InvalidDutyCycle,
+        /// No enabled channel streams from the FIFO, so it would never drain.
+        NotStreaming,
+        /// The PWM clock is not running yet.
+        NoClock,
+        /// The FIFO ran empty while a channel streamed with `Underrun::Silence`.
+        FifoUnderrun,
+        /// A word was written to the full FIFO and lost.
+        FifoOverrun,
+        /// The channel had no word to send for a period.
+        Gap(Channel),
+        /// The PWM's bus interface reported an error.
+        BusError,
+        /// The DMA engine feeding the FIFO failed.
+        Dma(super::dma::Error),
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Error::InvalidFrequency(hz) => write!(f, "Unsupported PWM frequency of {} Hz", hz),
+                Error::InvalidDutyCycle => f.write_str("PWM duty cycle not between 0 and 1"),
+                Error::NotStreaming => f.write_str("No PWM channel streams from the FIFO"),
+                Error::NoClock => f.write_str("PWM clock not running"),
+                Error::FifoUnderrun => f.write_str("PWM FIFO ran empty"),
+                Error::FifoOverrun => f.write_str("PWM FIFO overflowed"),
+                Error::Gap(channel) => write!(f, "Gap in the output of PWM {:?}", channel),
+                Error::BusError => f.write_str("PWM bus error"),
+                Error::Dma(error) => write!(f, "PWM DMA failed: {}", error),
+            }
+        }
+    }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    pub trait Control {
+        /// Set `channel`'s period to `frequency_hz`, or the closest the PWM clock allows, and
+        /// its duty cycle to `duty`, a fraction of the period from 0.0 to 1.0.
+        fn configure(&self, channel: Channel, frequency_hz: u32, duty: f32) -> Result<()>;
+
+        /// Change the duty cycle, keeping the frequency.
+        fn set_duty_cycle(&self, channel: Channel, duty: f32) -> Result<()>;
+
+        /// The frequency `channel` runs at, in Hz.
+        fn frequency(&self, channel: Channel) -> u32;
+
+        fn set_mode(&self, channel: Channel, mode: Mode);
+
+        fn set_polarity(&self, channel: Channel, polarity: Polarity);
+
+        fn enable(&self, channel: Channel);
+
+        /// Stop `channel`. Its pin then stays low, or high if inverted.
+        fn disable(&self, channel: Channel);
+    }
+
+    /// Streaming of sample words through the PWM FIFO, for waveforms a fixed duty cycle cannot
+    /// produce. Both channels share the FIFO: if both stream, they take its words in turns,
+    /// channel 1 first.
+    pub trait Stream {
+        /// Feed `channel` from the FIFO, emptying the FIFO and clearing pending errors. In
+        /// serialiser mode, the range is set to 32 so that whole words are sent.
+        fn start_stream(&self, channel: Channel, mode: StreamMode, underrun: Underrun);
+
+        /// Feed `channel` from its duty cycle again. Pending errors are discarded.
+        fn stop_stream(&self, channel: Channel);
+
+        /// The range of `channel`, the largest word in PWM mode.
+        fn range(&self, channel: Channel) -> u32;
+
+        /// Queue as many of `samples` as the FIFO has room for, without waiting, and return
+        /// the number queued. Fails with the first error reported since the last call, or with
+        /// `Error::NotStreaming` if the FIFO is full and no enabled channel streams from it.
+        fn write_samples(&self, samples: &[u32]) -> Result<usize>;
+
+        /// Queue all of `samples`, waiting for room in the FIFO. Drivers may hand long runs to a
+        /// DMA engine.
+        fn write_all_samples(&self, mut samples: &[u32]) -> Result<()> {
+            while !samples.is_empty() {
+                let queued = self.write_samples(samples)?;
+                samples = &samples[queued..];
+            }
+
+            Ok(())
+        }
+
+        /// Wait until every queued word was taken from the FIFO.
+        fn drain(&self) -> Result<()>;
+    }
 }
 
 pub mod gpio {
+    use core::fmt;
+
     #[allow(dead_code)]
     pub enum Pud {
         PudOff,
@@ -61,39 +193,358 @@ pub mod gpio {
         Output,
     }
 
+    /// What a pin is connected to. Which peripheral each alternate function selects depends on
+    /// the pin.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Function {
+        Input,
+        Output,
+        Alt0,
+        Alt1,
+        Alt2,
+        Alt3,
+        Alt4,
+        Alt5,
+    }
+
+    /// Reasons a GPIO request was rejected.
+    #[derive(Copy, Clone, Debug)]
+    pub enum Error {
+        /// The controller has no pin with this number.
+        InvalidPin(u32),
+        /// The pin cannot serve the requested peripheral.
+        UnsupportedFunction(u32),
+        /// The pin is owned by someone else, e.g. a UART the BSP routed to it.
+        PinInUse(u32),
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Error::InvalidPin(pin) => write!(f, "No GPIO pin {}", pin),
+                Error::UnsupportedFunction(pin) => {
+                    write!(f, "GPIO pin {} does not support the function", pin)
+                }
+                Error::PinInUse(pin) => write!(f, "GPIO pin {} is in use", pin),
+            }
+        }
+    }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// Pin-number based access. Pins that are claimed as owned pins are rejected with
+    /// `Error::PinInUse`, except for reading their level.
     pub trait Set {
-        fn pullupdn(&self, pin: u32, pud: Pud);
+        fn pullupdn(&self, pin: u32, pud: Pud) -> Result<()>;
 
-        fn setup(&self, pin: u32, direction: Dir, pud: Pud);
+        /// Make the pin an input or an output, driven low.
+        fn setup(&self, pin: u32, direction: Dir, pud: Pud) -> Result<()>;
 
-        fn setup_pwm(&self, pin: u32);
+        fn set_function(&self, pin: u32, function: Function) -> Result<()>;
 
+        /// Connect the pin to its PWM channel.
+        fn setup_pwm(&self, pin: u32) -> Result<()>;
+
+        /// Drive all unclaimed output pins low.
         fn cleanup(&self);
     }
 
     pub trait Output {
-        fn output(&self, pin: u32, value: u32);
+        fn output(&self, pin: u32, value: u32) -> Result<()>;
     }
 
     pub trait Input {
-        fn input(&self, pin: u32) -> u32;
+        fn input(&self, pin: u32) -> Result<u32>;
     }
 
     pub trait All = Set + Output + Input;
 }
 
+pub mod i2c {
+    use core::fmt;
+
+    /// Reasons an I2C transfer failed.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Error {
+        /// The address does not fit in 7 bits.
+        InvalidAddress(u8),
+        /// The bus cannot run at this speed.
+        InvalidSpeed(u32),
+        /// The transfer is longer than the controller can handle.
+        TooLong(usize),
+        /// The device did not acknowledge its address or a byte.
+        Nack(u8),
+        /// The device held the clock low for too long.
+        ClockStretchTimeout(u8),
+        /// The transfer did not finish in time.
+        Timeout(u8),
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Error::InvalidAddress(addr) => write!(f, "Invalid I2C address {:#x}", addr),
+                Error::InvalidSpeed(hz) => write!(f, "Unsupported I2C speed of {} Hz", hz),
+                Error::TooLong(len) => write!(f, "I2C transfer of {} bytes is too long", len),
+                Error::Nack(addr) => write!(f, "No acknowledge from I2C device {:#x}", addr),
+                Error::ClockStretchTimeout(addr) => {
+                    write!(f, "I2C device {:#x} stretched the clock too long", addr)
+                }
+                Error::Timeout(addr) => write!(f, "I2C transfer to {:#x} timed out", addr),
+            }
+        }
+    }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// An I2C bus master with 7-bit addressing.
+    pub trait Master {
+        /// Run the bus at `speed_hz`, or the next lower speed the controller supports.
+        fn set_speed(&self, speed_hz: u32) -> Result<()>;
+
+        fn write(&self, addr: u8, bytes: &[u8]) -> Result<()>;
+
+        fn read(&self, addr: u8, buffer: &mut [u8]) -> Result<()>;
+
+        /// Write `bytes`, then read into `buffer` after a repeated start, e.g. to read a device
+        /// register.
+        fn write_read(&self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<()>;
+
+        /// Call `found` with the address of every device that acknowledges a one byte read,
+        /// skipping the reserved addresses.
+        fn scan(&self, found: &mut dyn FnMut(u8)) {
+            for addr in 0x08..0x78 {
+                if self.read(addr, &mut [0]).is_ok() {
+                    found(addr);
+                }
+            }
+        }
+    }
+}
+
+pub mod spi {
+    use core::fmt;
+
+    /// Clock polarity and phase.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Mode {
+        /// Idle low, sample on the rising edge.
+        Mode0,
+        /// Idle low, sample on the falling edge.
+        Mode1,
+        /// Idle high, sample on the falling edge.
+        Mode2,
+        /// Idle high, sample on the rising edge.
+        Mode3,
+    }
+
+    /// The chip select line a device is wired to. Both are active low.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum ChipSelect {
+        Cs0,
+        Cs1,
+    }
+
+    /// Reasons an SPI request was rejected or a transfer failed.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Error {
+        /// The bus cannot run at this speed.
+        InvalidSpeed(u32),
+        /// The buffers to send and to receive into differ in length.
+        LengthMismatch(usize, usize),
+        /// The transfer did not finish in time.
+        Timeout,
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Error::InvalidSpeed(hz) => write!(f, "Unsupported SPI speed of {} Hz", hz),
+                Error::LengthMismatch(tx, rx) => {
+                    write!(f, "SPI transfer of {} bytes into a buffer of {}", tx, rx)
+                }
+                Error::Timeout => f.write_str("SPI transfer timed out"),
+            }
+        }
+    }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// A full-duplex SPI bus master. Speed and mode are kept per chip select.
+    pub trait Master {
+        /// Clock the device at `cs` with `speed_hz`, or the next lower speed the controller
+        /// supports.
+        fn set_speed(&self, cs: ChipSelect, speed_hz: u32) -> Result<()>;
+
+        fn set_mode(&self, cs: ChipSelect, mode: Mode);
+
+        /// Send `tx` to the device at `cs` while receiving the same number of bytes into `rx`.
+        fn transfer(&self, cs: ChipSelect, tx: &[u8], rx: &mut [u8]) -> Result<()>;
+
+        /// Send `tx` to the device at `cs`, dropping the bytes received.
+        fn write(&self, cs: ChipSelect, tx: &[u8]) -> Result<()>;
+    }
+}
+
+pub mod dma {
+    use core::{fmt, time::Duration};
+
+    /// Reasons a DMA transfer was rejected or failed.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Error {
+        /// The controller is not initialized.
+        Unavailable,
+        /// Every channel the controller may use is taken.
+        NoFreeChannel,
+        /// The control blocks do not fit into the DMA region.
+        NoMemory,
+        /// A segment is empty or has a peripheral at both ends.
+        InvalidSegment,
+        /// The channel still runs a transfer.
+        Busy,
+        /// The engine failed to read from the bus.
+        ReadError,
+        /// The engine's FIFO failed.
+        FifoError,
+        /// A peripheral did not mark the last word it sent.
+        ReadLastNotSet,
+        /// The transfer did not finish in time, and was aborted.
+        Timeout,
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(match self {
+                Error::Unavailable => "DMA controller not initialized",
+                Error::NoFreeChannel => "No free DMA channel",
+                Error::NoMemory => "No DMA memory for control blocks",
+                Error::InvalidSegment => "Invalid DMA segment",
+                Error::Busy => "DMA channel busy",
+                Error::ReadError => "DMA read error",
+                Error::FifoError => "DMA FIFO error",
+                Error::ReadLastNotSet => "DMA peripheral did not mark its last word",
+                Error::Timeout => "DMA transfer timed out",
+            })
+        }
+    }
+
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    /// One end of a transfer, at the address DMA engines see it at.
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum Endpoint {
+        /// Memory, walked upwards from the bus address, e.g. `DMABuffer::bus_addr()`.
+        Memory(usize),
+        /// The data register of a peripheral at the bus address, accessed a word at a time, and
+        /// the request line (DREQ) the peripheral paces the transfer with.
+        Peripheral(usize, u32),
+    }
+
+    /// `len` bytes from `src` to `dest`.
+    #[derive(Copy, Clone, Debug)]
+    pub struct Segment {
+        pub src: Endpoint,
+        pub dest: Endpoint,
+        pub len: usize,
+    }
+
+    pub trait Engine {
+        /// Run `segments` in order on a free channel and wait for them to finish, for at most
+        /// `timeout`.
+        ///
+        /// # Safety
+        ///
+        /// - The memory endpoints must be DMA-coherent and valid for the whole transfer.
+        unsafe fn transfer(&self, segments: &[Segment], timeout: Duration) -> Result<()>;
+    }
+}
+
 pub mod driver {
-    pub type Result = core::result::Result<(), ()>;
+    use super::gpio;
+    use crate::memory::MapError;
+    use core::fmt;
+
+    /// Reasons a driver failed to bring up its device.
+    #[derive(Copy, Clone, Debug)]
+    pub enum Error {
+        /// The device registers could not be mapped.
+        Map(MapError),
+        /// The device is unusable for the given reason.
+        Device(&'static str),
+        /// The device's pins could not be claimed.
+        Gpio(gpio::Error),
+    }
+
+    impl From<MapError> for Error {
+        fn from(err: MapError) -> Self {
+            Error::Map(err)
+        }
+    }
+
+    impl From<gpio::Error> for Error {
+        fn from(err: gpio::Error) -> Self {
+            Error::Gpio(err)
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Error::Map(err) => write!(f, "Mapping the registers failed: {}", err),
+                Error::Device(msg) => f.write_str(msg),
+                Error::Gpio(err) => write!(f, "Claiming the pins failed: {}", err),
+            }
+        }
+    }
+
+    pub type Result = core::result::Result<(), Error>;
 
     pub trait DeviceDriver {
+        /// The device tree `compatible` string of the devices the driver handles.
         fn compatible(&self) -> &str;
 
+        /// The `compatible()` strings of the drivers that must be initialized before this one.
+        fn dependencies(&self) -> &'static [&'static str] {
+            &[]
+        }
+
+        /// Use the registers at `phys_base_addr`, e.g. from the device tree, instead of the
+        /// compiled-in address. Must be called before `init()`.
+        fn set_phys_base_addr(&mut self, _phys_base_addr: usize) {}
+
+        /// The compiled-in address of the registers, for drivers of one of several devices that
+        /// share a `compatible()`. The device tree node is then matched by its address instead.
+        fn fixed_phys_base_addr(&self) -> Option<usize> {
+            None
+        }
+
         fn init(&self) -> Result {
             Ok(())
         }
     }
 }
 
+pub mod irq {
+    /// Interrupt controller functions.
+    pub trait Manager {
+        /// Let the interrupt through to the boot core.
+        fn enable(&self, irq: u32);
+
+        fn disable(&self, irq: u32);
+
+        /// Acknowledge the highest priority pending interrupt. `None` if there is none.
+        fn acknowledge(&self) -> Option<u32>;
+
+        /// Signal that the interrupt returned by `acknowledge()` has been handled.
+        fn end_of_interrupt(&self, irq: u32);
+    }
+}
+
 pub mod time {
     use core::time::Duration;
 
@@ -113,7 +564,50 @@ pub mod time {
 }
 
 pub mod mm {
+    use crate::memory::{AddressInfo, AttributeFields, MapError, MappedRange, TranslationFault};
+    use core::ops::RangeInclusive;
+
     pub trait MMU {
+        /// Turn on the MMU of the calling core.
+        ///
+        /// Runs from physical addresses. Afterwards, the lower half still maps the kernel so that
+        /// execution can continue until the core switches to the upper half.
+        unsafe fn enable(&self) -> Result<(), &'static str>;
+
+        /// Stop translating lower half addresses on the calling core, so that they fault until
+        /// a user address space is installed.
+        unsafe fn release_lower_half(&self);
+
+        /// Populate the page tables from the BSP's virtual memory layout.
         unsafe fn init(&self) -> Result<(), &'static str>;
+
+        /// Return the first page that is both writable and executable, if any.
+        fn find_writable_executable_page(&self) -> Option<usize>;
+
+        /// Translate `virt_addr` the way a load or store of the calling core would.
+        fn translate(&self, virt_addr: usize) -> Result<AddressInfo, TranslationFault>;
+
+        /// Walk the page tables and call `f` for every run of consecutively mapped pages.
+        ///
+        /// The tables are locked meanwhile, so `f` must not map, unmap or protect.
+        fn for_each_mapped_range(&self, f: &mut dyn FnMut(&MappedRange));
+
+        /// Map a currently unmapped virtual range to the physical range starting at `phys_addr`.
+        unsafe fn map(
+            &self,
+            virt_range: RangeInclusive<usize>,
+            phys_addr: usize,
+            attribute_fields: AttributeFields,
+        ) -> Result<(), MapError>;
+
+        /// Remove the mapping of a fully mapped virtual range.
+        unsafe fn unmap(&self, virt_range: RangeInclusive<usize>) -> Result<(), MapError>;
+
+        /// Change the attributes of a fully mapped virtual range, keeping its output addresses.
+        unsafe fn protect(
+            &self,
+            virt_range: RangeInclusive<usize>,
+            attribute_fields: AttributeFields,
+        ) -> Result<(), MapError>;
     }
 }
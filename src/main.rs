@@ -8,9 +8,15 @@
 #![feature(const_generics)]
 #![feature(asm)]
 #![feature(global_asm)]
+#![feature(alloc_error_handler)]
+
+extern crate alloc;
 
 mod arch;
 mod bsp;
+mod console;
+mod driver;
+mod fdt;
 mod interface;
 mod memory;
 mod multi_core;
@@ -19,22 +25,111 @@ mod print;
 mod runtime_init;
 
 use arch::{init_mmu, sleep};
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+use core::ptr;
 use core::time::Duration;
+use interface::console::{Read, Write};
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
 use interface::{
-    console::All as ConsoleAll,
     gpio::All as GPIOAll,
     gpio::{Dir, Pud},
-    pwm::All as PWMAll,
+    pwm::{Channel, Control},
 };
 
+/// Keeps the most recent kernel output, including what was logged before the UART was up.
+static KERNEL_LOG: console::LogBuffer = console::LogBuffer::new();
+
 unsafe fn kernel_init() {
+    console::console()
+        .add_sink(&KERNEL_LOG, console::LogLevel::Info)
+        .unwrap();
+
+    // The boot mapping still covers all of RAM, so the blob can be read wherever it is.
+    fdt::init(arch::boot_dtb_phys_addr().or(bsp::default_dtb_phys_addr()));
+    bsp::init_phys_memory_map();
+
     init_mmu();
-    for i in bsp::device_drivers().iter_mut() {
-        if let Err(()) = i.init() {
-            panic!("Error loading driver: {}", i.compatible())
+    bsp::register_device_drivers();
+    bsp::probe_device_drivers();
+    memory::heap::kernel_heap_allocator().init(bsp::kernel_heap_range());
+    memory::dma::init(bsp::dma_range());
+    // Failures are recorded per driver and listed once the console is up.
+    driver::driver_manager_mut().init_drivers();
+    // Catch up on what was logged while only the buffer listened.
+    KERNEL_LOG.write_to(bsp::console());
+    console::console()
+        .add_sink(bsp::console(), console::LogLevel::Info)
+        .unwrap();
+    console::console().set_input(bsp::console());
+
+    // The slave cores are woken only now, so they find the final page tables and a console.
+    bsp::activate_other_cores();
+}
+
+/// Board facts only the VideoCore firmware knows.
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+fn print_firmware_info() {
+    use bsp::driver::mailbox::ClockId;
+
+    let mailbox = bsp::mailbox();
+    info!("Firmware:");
+    match mailbox.get_board_revision() {
+        Ok(revision) => info!("      Board revision: {:#x}", revision),
+        Err(err) => warn!("      Board revision: {}", err),
+    }
+    match mailbox.get_arm_memory() {
+        Ok(region) => info!(
+            "      ARM memory:     {:#x} + {:#x}",
+            region.base, region.size
+        ),
+        Err(err) => warn!("      ARM memory: {}", err),
+    }
+    match mailbox.get_clock_rate(ClockId::Arm) {
+        Ok(rate) => info!("      ARM clock:      {} Hz", rate),
+        Err(err) => warn!("      ARM clock: {}", err),
+    }
+    match mailbox.get_temperature() {
+        Ok(temp) => info!("      Temperature:    {}.{:03} C", temp / 1000, temp % 1000),
+        Err(err) => warn!("      Temperature: {}", err),
+    }
+}
+
+/// Every device that answers on the I2C bus.
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+fn print_i2c_devices() {
+    use interface::i2c::Master;
+
+    info!("I2C devices:");
+    bsp::i2c().scan(&mut |addr| info!("      {:#04x}", addr));
+}
+
+/// Copy a pattern from one DMA buffer into another with the DMA controller.
+#[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+fn check_dma_copy() {
+    const LEN: usize = 256;
+
+    let (src, dest) = match (memory::dma::alloc(LEN, 32), memory::dma::alloc(LEN, 32)) {
+        (Some(src), Some(dest)) => (src, dest),
+        _ => {
+            warn!("DMA copy: no DMA memory");
+            return;
         }
+    };
+    for i in 0..LEN {
+        unsafe { ptr::write_volatile(src.as_mut_ptr().add(i), i as u8) };
+    }
+
+    match bsp::dma().copy(&dest, &src, Duration::from_millis(10)) {
+        Ok(()) => {
+            let byte = |i: usize| unsafe { ptr::read_volatile(dest.as_mut_ptr().add(i)) };
+            if (0..LEN).all(|i| byte(i) == i as u8) {
+                info!("DMA copy: {} bytes", LEN);
+            } else {
+                warn!("DMA copy: data differs");
+            }
+        }
+        Err(err) => warn!("DMA copy: {}", err),
     }
-    bsp::post_driver_init();
 }
 
 fn kernel_main() -> ! {
@@ -44,14 +139,47 @@ fn kernel_main() -> ! {
 
     info!("Hit ENTER to continue...");
     loop {
-        if bsp::console().read_char() == '\n' {
+        if console::console().read_char() == '\n' {
             break;
         }
     }
 
     info!("Booting on: {}", bsp::board_name());
 
+    match fdt::device_tree() {
+        Ok(tree) => {
+            info!("Device tree: {} bytes", tree.total_size());
+            if let Some(chosen) = tree.chosen() {
+                if let Some(bootargs) = chosen.bootargs() {
+                    info!("      Boot arguments: {}", bootargs);
+                }
+                if let Some(stdout) = chosen.stdout() {
+                    info!("      Console:        {}", stdout.name());
+                }
+            }
+        }
+        Err(err) => warn!("Device tree: {}", err),
+    }
+    info!("{}", memory::phys_map::phys_memory_map());
+
     info!("{}", bsp::virt_mem_layout());
+    memory::dump_page_tables();
+
+    // The kernel's code, and the unmapped lower half.
+    info!("Address translations:");
+    for &virt_addr in &[kernel_main as usize, 0] {
+        match memory::translate(virt_addr) {
+            Ok(address_info) => info!("      {}", address_info),
+            Err(fault) => info!("      {:#018x}: {}", virt_addr, fault),
+        }
+    }
+
+    info!("Kernel log buffer: {} bytes", KERNEL_LOG.len());
+
+    info!(
+        "Kernel heap: {}",
+        memory::heap::kernel_heap_allocator().stats()
+    );
 
     let (_, privilege_level) = arch::state::current_privilege_level();
     info!("Current privilege level: {}", privilege_level);
@@ -65,25 +193,52 @@ fn kernel_main() -> ! {
     );
 
     info!("Drivers loaded:");
-    for (i, driver) in bsp::device_drivers().iter().enumerate() {
-        info!("      {}. {}", i + 1, driver.compatible());
+    for (i, descriptor) in driver::driver_manager().descriptors().enumerate() {
+        let compatible = descriptor.driver().compatible();
+        match descriptor.node() {
+            Some(node) => info!("      {}. {} at {}", i + 1, compatible, node.name()),
+            None => info!("      {}. {} (compiled-in address)", i + 1, compatible),
+        }
+        info!("         status     {}", descriptor.status());
+        if let Some(node) = descriptor.node() {
+            for (addr, size) in node.reg() {
+                info!("         reg        {:#x} - {:#x}", addr, addr + size - 1);
+            }
+            for interrupt in node.interrupts() {
+                info!("         interrupts {}", interrupt);
+            }
+        }
     }
 
-    bsp::gpio().setup(1, Dir::Output, Pud::PudOff);
-    bsp::gpio().setup(2, Dir::Input, Pud::PudOff);
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    print_firmware_info();
 
-    bsp::gpio().setup_pwm(12);
-    bsp::pwm().write(12, 100);
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    print_i2c_devices();
+
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    check_dma_copy();
+
+    // The GPIO and PWM demos need a Raspberry Pi.
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    {
+        bsp::gpio().setup(1, Dir::Output, Pud::PudOff).unwrap();
+        bsp::gpio().setup(4, Dir::Input, Pud::PudOff).unwrap();
+
+        // The BSP owns the PWM pins. Channel 1 is on GPIO 12.
+        bsp::pwm().configure(Channel::Ch1, 1_000, 0.1).unwrap();
+        bsp::pwm().enable(Channel::Ch1);
+    }
 
     // crate::multi_core::submit_job_override(hello_world, 1);
 
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+    let mut led = bsp::take_gpio_pin(17).unwrap().into_output();
+
     let mut i = 0;
     loop {
-        if i % 2 == 0 {
-            bsp::gpio().output(17, 1);
-        } else {
-            bsp::gpio().output(17, 0);
-        }
+        #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
+        led.toggle();
         info!("Spinning for 1 second");
         sleep(Duration::from_secs(1));
         i += 1;
@@ -94,26 +249,35 @@ fn kernel_main() -> ! {
 
     let mut i = 0;
     loop {
-        info!(Echoing input)
-        let c = bsp::console().read_char();
-        bsp::console().write_char(c);
+        info!("Echoing input");
+        let c = console::console().read_char();
+        console::console().write_char(c);
         i += 1;
         if i == 10 {
             break;
         }
     }
 
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
     let mut i = 0;
+    #[cfg(any(feature = "bsp_rpi3", feature = "bsp_rpi4"))]
     loop {
-        if i % 2 == 0{
-            bsp::pwm().write(12, i * 64);
+        if i % 2 == 0 {
+            bsp::pwm()
+                .set_duty_cycle(Channel::Ch1, i as f32 / 16.0)
+                .unwrap();
         } else {
-            bsp::pwm().write(12, 1024 - i * 64);
+            bsp::pwm()
+                .set_duty_cycle(Channel::Ch1, 1.0 - i as f32 / 16.0)
+                .unwrap();
         }
         if i == 16 {
             break;
         }
+        i += 1;
     }
+
+    arch::wait_forever(arch::get_core_id())
 }
 
 #[allow(dead_code)]
@@ -125,6 +289,5 @@ fn hello_world() {
     for i in 0..10 {
         info!("{} th Hello world", i);
         sleep(Duration::from_millis(500));
-		
     }
 }
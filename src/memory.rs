@@ -1,8 +1,28 @@
+pub mod dma;
+pub mod heap;
+pub mod mmio;
+pub mod phys_map;
+
+use crate::{arch, bsp, info, interface::mm::MMU};
 use core::{
     fmt,
     ops::{Range, RangeInclusive},
 };
 
+/// Translate an address of the kernel's upper half to its physical address.
+///
+/// Also accepts a physical address and returns it unchanged, which makes it safe to use on
+/// symbol addresses before the MMU is on, no matter if they were computed PC-relative or loaded
+/// from the GOT.
+pub const fn kernel_virt_to_phys(virt_addr: usize) -> usize {
+    virt_addr & !bsp::KERNEL_VIRT_OFFSET
+}
+
+/// Translate a physical address to its alias in the kernel's upper half.
+pub const fn kernel_phys_to_virt(phys_addr: usize) -> usize {
+    phys_addr | bsp::KERNEL_VIRT_OFFSET
+}
+
 pub unsafe fn zero_volatile<T>(range: Range<*mut T>)
 where
     T: From<u8>,
@@ -19,12 +39,16 @@ where
 #[derive(Copy, Clone)]
 pub enum Translation {
     Identity,
+    /// The output address is the virtual address minus the kernel's upper half offset.
+    KernelLinear,
     Offset(usize),
 }
 
 #[derive(Copy, Clone)]
 pub enum MemAttributes {
     CacheableDRAM,
+    /// Normal memory that bypasses the caches, for buffers shared with DMA capable devices.
+    NonCacheable,
     Device,
 }
 
@@ -41,6 +65,17 @@ pub struct AttributeFields {
     pub execute_never: bool,
 }
 
+impl AttributeFields {
+    /// Whether memory with these attributes could be modified and then executed (W^X violation).
+    ///
+    /// A `const fn`, so that the attributes of the layout can be checked at compile time.
+    pub const fn is_writable_and_executable(&self) -> bool {
+        let writable = self.acc_perms as usize == AccessPermissions::ReadWrite as usize;
+
+        writable & !self.execute_never
+    }
+}
+
 impl Default for AttributeFields {
     fn default() -> Self {
         Self {
@@ -51,6 +86,78 @@ impl Default for AttributeFields {
     }
 }
 
+/// Errors returned by the runtime page mapping API.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MapError {
+    /// The range is empty.
+    EmptyRange,
+    /// Start, end or output address is not aligned to the page size.
+    Misaligned,
+    /// The range reaches beyond the address space covered by the page tables.
+    OutOfRange,
+    /// At least one page of the range is already mapped.
+    AlreadyMapped,
+    /// At least one page of the range is not mapped.
+    NotMapped,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            MapError::EmptyRange => "Empty range",
+            MapError::Misaligned => "Address not page aligned",
+            MapError::OutOfRange => "Range out of bound",
+            MapError::AlreadyMapped => "Range overlaps an existing mapping",
+            MapError::NotMapped => "Range is not mapped",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Reasons for rejecting a `KernelVirtualLayout`.
+#[derive(Copy, Clone, Debug)]
+pub enum LayoutError {
+    /// Start or end of the named range is not aligned to the page size.
+    Misaligned(&'static str),
+    /// The named range ends beyond the layout's maximum virtual address.
+    OutOfBound(&'static str),
+    /// The two named ranges share at least one address.
+    Overlap(&'static str, &'static str),
+    /// The named range is both writable and executable.
+    WritableAndExecutable(&'static str),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::Misaligned(name) => write!(f, "\"{}\" is not page aligned", name),
+            LayoutError::OutOfBound(name) => {
+                write!(f, "\"{}\" exceeds the maximum virtual address", name)
+            }
+            LayoutError::Overlap(a, b) => write!(f, "\"{}\" overlaps \"{}\"", a, b),
+            LayoutError::WritableAndExecutable(name) => {
+                write!(f, "\"{}\" is both writable and executable", name)
+            }
+        }
+    }
+}
+
+/// Whether `start..=end_inclusive` starts and ends on page boundaries.
+///
+/// A `const fn`, so that ranges built from constants can be checked at compile time with
+/// `const_assert!`.
+pub const fn is_page_aligned_range(start: usize, end_inclusive: usize, page_size: usize) -> bool {
+    ((start | (end_inclusive + 1)) & (page_size - 1)) == 0
+}
+
+/// Fail compilation if the given constant expression is false.
+#[macro_export]
+macro_rules! const_assert {
+    ($cond:expr) => {
+        const _: [(); 1] = [(); ($cond) as usize];
+    };
+}
+
 pub struct RangeDescriptor {
     pub name: &'static str,
     pub virtual_range: fn() -> RangeInclusive<usize>,
@@ -58,52 +165,156 @@ pub struct RangeDescriptor {
     pub attribute_fields: AttributeFields,
 }
 
+/// Print a range in the format shared by the virtual memory layout and the page table dump.
+fn fmt_range(
+    f: &mut fmt::Formatter,
+    virt_range: &RangeInclusive<usize>,
+    attribute_fields: &AttributeFields,
+    name: fmt::Arguments,
+) -> fmt::Result {
+    let start = *virt_range.start();
+    let end = *virt_range.end();
+    let size = end - start + 1;
+
+    // log2(1024).
+    const KIB_RSHIFT: u32 = 10;
+
+    // log2(1024 * 1024).
+    const MIB_RSHIFT: u32 = 20;
+
+    let (size, unit) = if (size >> MIB_RSHIFT) > 0 {
+        (size >> MIB_RSHIFT, "MiB")
+    } else if (size >> KIB_RSHIFT) > 0 {
+        (size >> KIB_RSHIFT, "KiB")
+    } else {
+        (size, "Byte")
+    };
+
+    let attr = match attribute_fields.mem_attributes {
+        MemAttributes::CacheableDRAM => "C",
+        MemAttributes::NonCacheable => "NC",
+        MemAttributes::Device => "Dev",
+    };
+
+    let acc_p = match attribute_fields.acc_perms {
+        AccessPermissions::ReadOnly => "RO",
+        AccessPermissions::ReadWrite => "RW",
+    };
+
+    let xn = if attribute_fields.execute_never {
+        "PXN"
+    } else {
+        "PX"
+    };
+
+    write!(
+        f,
+        "      {:#018x} - {:#018x} | {: >3} {} | {: <3} {} {: <3} | {}",
+        start, end, size, unit, attr, acc_p, xn, name
+    )
+}
+
 impl fmt::Display for RangeDescriptor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // Call the function to which self.range points, and dereference the result, which causes
-        // Rust to copy the value.
-        let start = *(self.virtual_range)().start();
-        let end = *(self.virtual_range)().end();
-        let size = end - start + 1;
-
-        // log2(1024).
-        const KIB_RSHIFT: u32 = 10;
-
-        // log2(1024 * 1024).
-        const MIB_RSHIFT: u32 = 20;
-
-        let (size, unit) = if (size >> MIB_RSHIFT) > 0 {
-            (size >> MIB_RSHIFT, "MiB")
-        } else if (size >> KIB_RSHIFT) > 0 {
-            (size >> KIB_RSHIFT, "KiB")
-        } else {
-            (size, "Byte")
-        };
+        fmt_range(
+            f,
+            &(self.virtual_range)(),
+            &self.attribute_fields,
+            format_args!("{}", self.name),
+        )
+    }
+}
+
+/// A run of consecutive pages that the page tables map to consecutive physical pages with the
+/// same attributes.
+pub struct MappedRange {
+    pub virt_range: RangeInclusive<usize>,
+    pub phys_start: usize,
+    pub attribute_fields: AttributeFields,
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_range(
+            f,
+            &self.virt_range,
+            &self.attribute_fields,
+            format_args!("-> {:#018x}", self.phys_start),
+        )
+    }
+}
+
+/// What the MMU reports for a virtual address that translates successfully.
+#[derive(Copy, Clone)]
+pub struct AddressInfo {
+    pub virt_addr: usize,
+    pub phys_addr: usize,
+    pub mem_attributes: MemAttributes,
+    /// `ReadWrite` if a write to the address would translate as well.
+    pub acc_perms: AccessPermissions,
+}
 
-        let attr = match self.attribute_fields.mem_attributes {
+impl fmt::Display for AddressInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let attr = match self.mem_attributes {
             MemAttributes::CacheableDRAM => "C",
+            MemAttributes::NonCacheable => "NC",
             MemAttributes::Device => "Dev",
         };
 
-        let acc_p = match self.attribute_fields.acc_perms {
+        let acc_p = match self.acc_perms {
             AccessPermissions::ReadOnly => "RO",
             AccessPermissions::ReadWrite => "RW",
         };
 
-        let xn = if self.attribute_fields.execute_never {
-            "PXN"
-        } else {
-            "PX"
-        };
-
         write!(
             f,
-            "      {:#010x} - {:#010x} | {: >3} {} | {: <3} {} {: <3} | {}",
-            start, end, size, unit, attr, acc_p, xn, self.name
+            "{:#018x} -> {:#018x} | {: <3} {}",
+            self.virt_addr, self.phys_addr, attr, acc_p
         )
     }
 }
 
+/// Why the MMU could not translate a virtual address. Levels are those of the table walk.
+#[derive(Copy, Clone, Debug)]
+pub enum TranslationFault {
+    AddressSize(u8),
+    Translation(u8),
+    AccessFlag(u8),
+    Permission(u8),
+    /// Any other fault, with the architecture specific status code.
+    Other(u8),
+}
+
+impl fmt::Display for TranslationFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranslationFault::AddressSize(level) => {
+                write!(f, "Address size fault at level {}", level)
+            }
+            TranslationFault::Translation(level) => {
+                write!(f, "Translation fault at level {}", level)
+            }
+            TranslationFault::AccessFlag(level) => {
+                write!(f, "Access flag fault at level {}", level)
+            }
+            TranslationFault::Permission(level) => write!(f, "Permission fault at level {}", level),
+            TranslationFault::Other(status) => write!(f, "Fault with status {:#04x}", status),
+        }
+    }
+}
+
+/// Ask the MMU how it translates `virt_addr` for the calling core.
+pub fn translate(virt_addr: usize) -> Result<AddressInfo, TranslationFault> {
+    arch::mmu().translate(virt_addr)
+}
+
+/// Print every mapped range of the page tables.
+pub fn dump_page_tables() {
+    info!("Page tables:");
+    arch::mmu().for_each_mapped_range(&mut |range| info!("{}", range));
+}
+
 pub struct KernelVirtualLayout<const NUM_SPECIAL_RANGES: usize> {
     max_virt_addr_inclusive: usize,
     inner: [RangeDescriptor; NUM_SPECIAL_RANGES],
@@ -116,10 +327,49 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
             inner: layout,
         }
     }
+
+    /// Reject layouts with misaligned or out of bound ranges, overlapping ranges (which would
+    /// silently shadow each other) and ranges that are both writable and executable.
+    pub fn validate(&self, page_size: usize) -> Result<(), LayoutError> {
+        for (i, a) in self.inner.iter().enumerate() {
+            let range_a = (a.virtual_range)();
+
+            if !is_page_aligned_range(*range_a.start(), *range_a.end(), page_size) {
+                return Err(LayoutError::Misaligned(a.name));
+            }
+
+            if let Translation::Offset(output_addr) = a.translation {
+                if (output_addr & (page_size - 1)) != 0 {
+                    return Err(LayoutError::Misaligned(a.name));
+                }
+            }
+
+            if *range_a.end() > self.max_virt_addr_inclusive {
+                return Err(LayoutError::OutOfBound(a.name));
+            }
+
+            if a.attribute_fields.is_writable_and_executable() {
+                return Err(LayoutError::WritableAndExecutable(a.name));
+            }
+
+            for b in self.inner.iter().skip(i + 1) {
+                let range_b = (b.virtual_range)();
+
+                if range_a.start() <= range_b.end() && range_b.start() <= range_a.end() {
+                    return Err(LayoutError::Overlap(a.name, b.name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Output address and attributes of the page at `virt_addr`, or `None` if no range covers it
+    /// and the page must stay unmapped.
     pub fn get_virtual_addr_properties(
         &self,
         virt_addr: usize,
-    ) -> Result<(usize, AttributeFields), &'static str> {
+    ) -> Result<Option<(usize, AttributeFields)>, &'static str> {
         if virt_addr > self.max_virt_addr_inclusive {
             return Err("Address out of bound");
         }
@@ -127,17 +377,17 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
             if (i.virtual_range)().contains(&virt_addr) {
                 let output_addr = match i.translation {
                     Translation::Identity => virt_addr,
+                    Translation::KernelLinear => kernel_virt_to_phys(virt_addr),
                     Translation::Offset(a) => a + (virt_addr - (i.virtual_range)().start()),
                 };
-                return Ok((output_addr, i.attribute_fields));
+                return Ok(Some((output_addr, i.attribute_fields)));
             }
         }
-        Ok((virt_addr, AttributeFields::default()))
+        Ok(None)
     }
+
     #[allow(dead_code)]
     pub fn print_layout(&self) {
-        use crate::info;
-
         for i in self.inner.iter() {
             info!("{}", i);
         }
@@ -146,7 +396,11 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
 
 impl<const NUM_SPECIAL_RANGES: usize> fmt::Display for KernelVirtualLayout<{ NUM_SPECIAL_RANGES }> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Max Virtual Address: {}", self.max_virt_addr_inclusive)?;
+        writeln!(
+            f,
+            "Max Virtual Address: {:#018x}",
+            self.max_virt_addr_inclusive
+        )?;
         for i in self.inner.iter() {
             writeln!(f, "{}", i)?;
         }
@@ -0,0 +1,96 @@
+use super::{
+    heap::{HeapAllocator, HeapStats},
+    kernel_virt_to_phys,
+};
+use crate::{arch, bsp};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::RangeInclusive,
+    ptr,
+};
+
+/// Hands out buffers from the BSP's non-cacheable DMA region.
+static DMA_ALLOCATOR: HeapAllocator = HeapAllocator::new();
+
+/// A buffer in non-cacheable memory, so that the CPU and DMA capable devices always see the same
+/// data without cache maintenance. Freed when dropped.
+///
+/// Devices access the buffer through `bus_addr()`. The device may write the buffer at any time
+/// while it owns it, so the CPU should access it with volatile reads and writes.
+pub struct DMABuffer {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+// The buffer is owned exclusively by its handle.
+unsafe impl Send for DMABuffer {}
+
+#[allow(dead_code)]
+impl DMABuffer {
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    pub fn virt_addr(&self) -> usize {
+        self.ptr as usize
+    }
+
+    pub fn phys_addr(&self) -> usize {
+        kernel_virt_to_phys(self.virt_addr())
+    }
+
+    /// The address to program into DMA engines and VideoCore messages.
+    pub fn bus_addr(&self) -> usize {
+        bsp::phys_to_bus(self.phys_addr())
+    }
+}
+
+impl Drop for DMABuffer {
+    fn drop(&mut self) {
+        unsafe { DMA_ALLOCATOR.dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// Hand the DMA region to the allocator.
+///
+/// Cache lines of the region that were allocated while it was still mapped cacheable by the boot
+/// mapping are written back and discarded, so that they cannot be evicted over device writes
+/// later on.
+///
+/// # Safety
+///
+/// - The region must be mapped non-cacheable, RW, and must not be used by anything else.
+/// - Must only be called once.
+pub unsafe fn init(region: RangeInclusive<usize>) {
+    arch::cache::clean_invalidate_dcache_range(*region.start()..*region.end() + 1);
+
+    DMA_ALLOCATOR.init(region);
+}
+
+/// Allocate a zeroed DMA-coherent buffer.
+#[allow(dead_code)]
+pub fn alloc(size: usize, align: usize) -> Option<DMABuffer> {
+    let layout = Layout::from_size_align(size, align).ok()?;
+    if layout.size() == 0 {
+        return None;
+    }
+
+    let ptr = unsafe { DMA_ALLOCATOR.alloc(layout) };
+    if ptr.is_null() {
+        return None;
+    }
+
+    unsafe { ptr::write_bytes(ptr, 0, layout.size()) };
+
+    Some(DMABuffer { ptr, layout })
+}
+
+/// Usage counters of the DMA region.
+#[allow(dead_code)]
+pub fn stats() -> HeapStats {
+    DMA_ALLOCATOR.stats()
+}
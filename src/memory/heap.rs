@@ -0,0 +1,261 @@
+use crate::arch::Mutex;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt, mem,
+    ops::RangeInclusive,
+    ptr,
+};
+
+/// Header placed at the start of every free block. Free blocks are kept in a singly linked list
+/// sorted by address, so that neighbours can be merged again on deallocation.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Every block handed out or kept on the free list is a multiple of this, so that a free block
+/// header always fits into whatever is left over after a split.
+const BLOCK_ALIGN: usize = mem::size_of::<FreeBlock>();
+
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// The size and alignment a request is actually served with.
+fn block_layout(layout: &Layout) -> (usize, usize) {
+    let align = if layout.align() > BLOCK_ALIGN {
+        layout.align()
+    } else {
+        BLOCK_ALIGN
+    };
+    let size = align_up(layout.size().max(BLOCK_ALIGN), BLOCK_ALIGN);
+
+    (size, align)
+}
+
+/// Usage counters of the kernel heap.
+#[derive(Copy, Clone)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub peak_used: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub failed_allocations: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} KiB used (peak {} KiB), {} allocs, {} frees, {} failed",
+            self.used >> 10,
+            self.size >> 10,
+            self.peak_used >> 10,
+            self.allocations,
+            self.deallocations,
+            self.failed_allocations
+        )
+    }
+}
+
+struct HeapInner {
+    free_list: *mut FreeBlock,
+    stats: HeapStats,
+}
+
+// The free list only ever points into the heap region, which is owned by the allocator.
+unsafe impl Send for HeapInner {}
+
+impl HeapInner {
+    const fn new() -> HeapInner {
+        HeapInner {
+            free_list: ptr::null_mut(),
+            stats: HeapStats {
+                size: 0,
+                used: 0,
+                peak_used: 0,
+                allocations: 0,
+                deallocations: 0,
+                failed_allocations: 0,
+            },
+        }
+    }
+
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        let block = start as *mut FreeBlock;
+        ptr::write(
+            block,
+            FreeBlock {
+                size,
+                next: ptr::null_mut(),
+            },
+        );
+
+        self.free_list = block;
+        self.stats.size = size;
+    }
+
+    /// First fit. Any padding in front of the allocation stays on the free list.
+    unsafe fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut cur = self.free_list;
+
+        while !cur.is_null() {
+            let block_start = cur as usize;
+            let block_end = block_start + (*cur).size;
+            let alloc_start = align_up(block_start, align);
+            let alloc_end = alloc_start + size;
+
+            if alloc_end <= block_end {
+                let next = (*cur).next;
+
+                // Whatever is left behind the allocation becomes a new free block.
+                let tail = if alloc_end < block_end {
+                    let tail = alloc_end as *mut FreeBlock;
+                    ptr::write(
+                        tail,
+                        FreeBlock {
+                            size: block_end - alloc_end,
+                            next,
+                        },
+                    );
+                    tail
+                } else {
+                    next
+                };
+
+                if alloc_start > block_start {
+                    // Keep the front padding as a shrunken version of the current block.
+                    (*cur).size = alloc_start - block_start;
+                    (*cur).next = tail;
+                } else if prev.is_null() {
+                    self.free_list = tail;
+                } else {
+                    (*prev).next = tail;
+                }
+
+                return alloc_start as *mut u8;
+            }
+
+            prev = cur;
+            cur = (*cur).next;
+        }
+
+        ptr::null_mut()
+    }
+
+    /// Insert the block back into the address-sorted free list and merge it with its neighbours.
+    unsafe fn dealloc(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.free_list;
+
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = addr as *mut FreeBlock;
+        ptr::write(block, FreeBlock { size, next });
+
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.free_list = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public
+//--------------------------------------------------------------------------------------------------
+
+/// A thread-safe, first-fit, linked-list heap allocator.
+pub struct HeapAllocator {
+    inner: Mutex<HeapInner>,
+}
+
+impl HeapAllocator {
+    pub const fn new() -> HeapAllocator {
+        HeapAllocator {
+            inner: Mutex::new(HeapInner::new()),
+        }
+    }
+
+    /// Hand the given memory region to the allocator.
+    ///
+    /// # Safety
+    ///
+    /// - The region must be mapped RW and must not be used by anything else.
+    /// - Must only be called once.
+    pub unsafe fn init(&self, region: RangeInclusive<usize>) {
+        let start = align_up(*region.start(), BLOCK_ALIGN);
+        let end_exclusive = (*region.end() + 1) & !(BLOCK_ALIGN - 1);
+
+        let mut inner = self.inner.lock();
+        if inner.stats.size != 0 {
+            panic!("Heap initialized twice");
+        }
+        inner.init(start, end_exclusive - start);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.inner.lock().stats
+    }
+}
+
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_layout(&layout);
+
+        let mut inner = self.inner.lock();
+        let ptr = inner.alloc(size, align);
+
+        if ptr.is_null() {
+            inner.stats.failed_allocations += 1;
+        } else {
+            inner.stats.used += size;
+            inner.stats.allocations += 1;
+            if inner.stats.used > inner.stats.peak_used {
+                inner.stats.peak_used = inner.stats.used;
+            }
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block_layout(&layout);
+
+        let mut inner = self.inner.lock();
+        inner.dealloc(ptr as usize, size);
+        inner.stats.used -= size;
+        inner.stats.deallocations += 1;
+    }
+}
+
+#[global_allocator]
+static KERNEL_HEAP_ALLOCATOR: HeapAllocator = HeapAllocator::new();
+
+/// Return a reference to the kernel's heap allocator.
+pub fn kernel_heap_allocator() -> &'static HeapAllocator {
+    &KERNEL_HEAP_ALLOCATOR
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!(
+        "Kernel heap allocation of {} bytes with alignment {} failed. Heap: {}",
+        layout.size(),
+        layout.align(),
+        KERNEL_HEAP_ALLOCATOR.stats()
+    );
+}
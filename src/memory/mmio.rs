@@ -0,0 +1,110 @@
+use super::{AccessPermissions, AttributeFields, MapError, MemAttributes};
+use crate::{
+    arch::{self, Mutex},
+    bsp,
+    interface::mm::MMU,
+};
+use core::marker::PhantomData;
+
+/// A device window mapped by `ioremap()`.
+///
+/// `T` is the register block drivers access through `ptr()`. The handle only describes the
+/// mapping, so copying it does not map anything twice.
+pub struct MMIOMapping<T> {
+    phys_addr: usize,
+    virt_addr: usize,
+    size: usize,
+    register_block: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for MMIOMapping<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for MMIOMapping<T> {}
+
+impl<T> MMIOMapping<T> {
+    /// Describe an existing mapping.
+    ///
+    /// # Safety
+    ///
+    /// - `virt_addr` must map `size` bytes starting at `phys_addr` as device memory.
+    pub const unsafe fn new(phys_addr: usize, virt_addr: usize, size: usize) -> Self {
+        Self {
+            phys_addr,
+            virt_addr,
+            size,
+            register_block: PhantomData,
+        }
+    }
+
+    pub fn ptr(&self) -> *const T {
+        self.virt_addr as *const _
+    }
+
+    #[allow(dead_code)]
+    pub fn phys_addr(&self) -> usize {
+        self.phys_addr
+    }
+
+    #[allow(dead_code)]
+    pub fn virt_addr(&self) -> usize {
+        self.virt_addr
+    }
+
+    #[allow(dead_code)]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The address DMA engines see the window at.
+    pub fn bus_addr(&self) -> usize {
+        bsp::mmio_phys_to_bus(self.phys_addr)
+    }
+}
+
+/// Bytes handed out from the start of the BSP's ioremap area. Mappings are never released, so a
+/// bump pointer is all it takes.
+static IOREMAP_USED: Mutex<usize> = Mutex::new(0);
+
+/// Map `size` bytes of device memory at `phys_addr` as Device-nGnRE, RW and XN into the ioremap
+/// area, and return a handle to the mapping.
+///
+/// Works at page granularity, so other registers sharing the first or last page become
+/// accessible as well.
+///
+/// # Safety
+///
+/// - `phys_addr..phys_addr + size` must be device memory.
+/// - The page tables must have been populated from the virtual memory layout, before which the
+///   ioremap area is still covered by the boot mapping.
+pub unsafe fn ioremap<T>(phys_addr: usize, size: usize) -> Result<MMIOMapping<T>, MapError> {
+    if size == 0 {
+        return Err(MapError::EmptyRange);
+    }
+
+    let page_offset = phys_addr & (arch::PAGE_SIZE - 1);
+    let map_size = (page_offset + size + (arch::PAGE_SIZE - 1)) & !(arch::PAGE_SIZE - 1);
+
+    let area = bsp::ioremap_range();
+    let mut used = IOREMAP_USED.lock();
+    if map_size > (area.end() - area.start() + 1) - *used {
+        return Err(MapError::OutOfRange);
+    }
+
+    let virt_start = area.start() + *used;
+    arch::mmu().map(
+        virt_start..=virt_start + (map_size - 1),
+        phys_addr - page_offset,
+        AttributeFields {
+            mem_attributes: MemAttributes::Device,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+        },
+    )?;
+    *used += map_size;
+
+    Ok(MMIOMapping::new(phys_addr, virt_start + page_offset, size))
+}
@@ -0,0 +1,140 @@
+use core::{fmt, ops::RangeInclusive};
+use spin::Once;
+
+/// Maximum number of regions of each kind the map keeps. Further regions are dropped.
+const MAX_REGIONS: usize = 8;
+
+/// Where the map came from.
+#[derive(Copy, Clone)]
+pub enum PhysMemorySource {
+    DeviceTree,
+    /// Compiled-in defaults of the BSP, used when the firmware did not describe the memory.
+    Default,
+}
+
+/// A fixed-capacity list of physical address ranges.
+struct Regions {
+    inner: [RangeInclusive<usize>; MAX_REGIONS],
+    len: usize,
+}
+
+impl Regions {
+    const fn new() -> Self {
+        Self {
+            inner: [0..=0, 0..=0, 0..=0, 0..=0, 0..=0, 0..=0, 0..=0, 0..=0],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, region: RangeInclusive<usize>) {
+        if self.len < MAX_REGIONS {
+            self.inner[self.len] = region;
+            self.len += 1;
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &RangeInclusive<usize>> {
+        self.inner[..self.len].iter()
+    }
+}
+
+/// The physical RAM of the board and the parts of it the kernel must not touch.
+pub struct PhysMemoryMap {
+    source: PhysMemorySource,
+    ram: Regions,
+    reserved: Regions,
+}
+
+impl PhysMemoryMap {
+    pub const fn new(source: PhysMemorySource) -> Self {
+        Self {
+            source,
+            ram: Regions::new(),
+            reserved: Regions::new(),
+        }
+    }
+
+    /// Add a RAM bank. Empty banks are ignored.
+    pub fn add_ram(&mut self, start: usize, size: usize) {
+        if size != 0 {
+            self.ram.push(start..=start + (size - 1));
+        }
+    }
+
+    /// Add a region that must not be used. Empty regions are ignored.
+    pub fn add_reserved(&mut self, start: usize, size: usize) {
+        if size != 0 {
+            self.reserved.push(start..=start + (size - 1));
+        }
+    }
+
+    pub fn has_ram(&self) -> bool {
+        self.ram.len != 0
+    }
+
+    /// The last address of the free memory that starts at `start`: the end of the RAM bank
+    /// containing `start`, or the byte before the first reserved region above `start`, whichever
+    /// comes first. `None` if `start` itself is not free.
+    pub fn free_end_inclusive(&self, start: usize) -> Option<usize> {
+        let bank_end = *self.ram.iter().find(|bank| bank.contains(&start))?.end();
+
+        if self.reserved.iter().any(|region| region.contains(&start)) {
+            return None;
+        }
+
+        Some(
+            self.reserved
+                .iter()
+                .filter(|region| *region.start() > start)
+                .map(|region| *region.start() - 1)
+                .fold(bank_end, usize::min),
+        )
+    }
+}
+
+impl fmt::Display for PhysMemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = match self.source {
+            PhysMemorySource::DeviceTree => "device tree",
+            PhysMemorySource::Default => "compiled-in defaults",
+        };
+        writeln!(f, "Physical memory map (from {}):", source)?;
+
+        for bank in self.ram.iter() {
+            writeln!(
+                f,
+                "      {:#018x} - {:#018x} | RAM",
+                bank.start(),
+                bank.end()
+            )?;
+        }
+        for region in self.reserved.iter() {
+            writeln!(
+                f,
+                "      {:#018x} - {:#018x} | Reserved",
+                region.start(),
+                region.end()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+static PHYS_MEMORY_MAP: Once<PhysMemoryMap> = Once::new();
+
+/// Publish the map. Later calls are ignored.
+pub fn init(map: PhysMemoryMap) {
+    PHYS_MEMORY_MAP.call_once(|| map);
+}
+
+/// Return the map.
+///
+/// # Panics
+///
+/// - If `init()` has not been called yet.
+pub fn phys_memory_map() -> &'static PhysMemoryMap {
+    PHYS_MEMORY_MAP
+        .r#try()
+        .expect("Physical memory map used before it was discovered")
+}
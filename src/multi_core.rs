@@ -1,4 +1,4 @@
-use crate::arch::{self, init_mmu, sleep, Mutex};
+use crate::arch::{self, sleep, Mutex};
 use crate::info;
 use core::result::Result;
 use core::time::Duration;
@@ -12,7 +12,6 @@ static JOB_PTRS: [Mutex<Option<fn()>>; 4] = [
 
 pub unsafe fn other_cores_main() -> ! {
     let id = arch::get_core_id() as usize;
-    init_mmu();
     info!("Core {} init finished.", id);
 
     loop {
@@ -1,8 +1,13 @@
+use crate::console::{self, LogLevel};
 use core::fmt;
 
 pub fn _print(args: fmt::Arguments) {
-    use crate::interface::console::Write;
-    crate::bsp::console().write_fmt(args).unwrap();
+    _log(LogLevel::Info, args);
+}
+
+/// Write to the console sinks that take messages of `level`.
+pub fn _log(level: LogLevel, args: fmt::Arguments) {
+    console::console().write_log(level, args).unwrap();
 }
 
 // Crate copied and modified from rust source code.
@@ -29,7 +34,7 @@ macro_rules! log {
         let timestamp = $crate::arch::timer().uptime();
         let timestamp_subsec_us = timestamp.subsec_micros();
 
-        $crate::print::_print(format_args_nl!(
+        $crate::print::_log($log_level, format_args_nl!(
             concat!("[{} {:>3}.{:03}{:03}] ", $format_string),
             $log_level,
             timestamp.as_secs(),
@@ -44,10 +49,10 @@ macro_rules! log {
 #[macro_export]
 macro_rules! info {
     ($string:expr) => ({
-        crate::log!("Info", "{}", $string);
+        crate::log!($crate::console::LogLevel::Info, "{}", $string);
     });
     ($format_string:expr, $($arg:tt)*) => ({
-        crate::log!("Info", $format_string, $($arg)*);
+        crate::log!($crate::console::LogLevel::Info, $format_string, $($arg)*);
     })
 }
 
@@ -55,9 +60,9 @@ macro_rules! info {
 #[macro_export]
 macro_rules! warn {
     ($string:expr) => ({
-        crate::log!("Warn", "{}", $string);
+        crate::log!($crate::console::LogLevel::Warn, "{}", $string);
     });
     ($format_string:expr, $($arg:tt)*) => ({
-        crate::log!("Warn", $format_string, $($arg)*);
+        crate::log!($crate::console::LogLevel::Warn, $format_string, $($arg)*);
     })
 }
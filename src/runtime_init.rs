@@ -1,6 +1,9 @@
-use crate::memory;
+use crate::{arch, memory};
 use core::ops::Range;
 
+/// The `.bss` range, as physical addresses.
+///
+/// Zeroing happens before the MMU is on, while the linker symbols are upper half addresses.
 unsafe fn bss_range() -> Range<*mut usize> {
     extern "C" {
         static mut __bss_start: usize;
@@ -8,8 +11,8 @@ unsafe fn bss_range() -> Range<*mut usize> {
     }
 
     Range {
-        start: &mut __bss_start,
-        end: &mut __bss_end,
+        start: memory::kernel_virt_to_phys(&mut __bss_start as *mut usize as usize) as *mut usize,
+        end: memory::kernel_virt_to_phys(&mut __bss_end as *mut usize as usize) as *mut usize,
     }
 }
 
@@ -21,9 +24,22 @@ unsafe fn zero_bss() {
 pub unsafe fn master_core_init() -> ! {
     zero_bss();
 
+    arch::populate_boot_page_tables();
+    arch::enable_mmu_and_switch_to_high_half(master_core_init_high_half)
+}
+
+unsafe fn master_core_init_high_half() -> ! {
+    arch::release_lower_half();
+
     crate::kernel_main()
 }
 
 pub unsafe fn other_cores_init() -> ! {
+    arch::enable_mmu_and_switch_to_high_half(other_cores_init_high_half)
+}
+
+unsafe fn other_cores_init_high_half() -> ! {
+    arch::release_lower_half();
+
     crate::multi_core::other_cores_main()
 }